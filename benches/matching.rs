@@ -0,0 +1,83 @@
+//! Compares looking up file names with `Vec::contains`, as finder used to,
+//! against the set-based [`Matcher`].
+//!
+//! Run with `cargo bench`. The number of list entries and file names can be
+//! given as arguments: `cargo bench -- 200000 5000000`.
+
+use finder::{MatchOptions, Matcher, Normalization};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// How many lookups the `Vec::contains` baseline is timed over, as running
+/// it over every name would take hours with large lists.
+const BASELINE_SAMPLE: usize = 2_000;
+
+fn main() {
+    let mut args = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse::<usize>().ok());
+    let entry_count = args.next().unwrap_or(200_000);
+    let name_count = args.next().unwrap_or(1_000_000);
+
+    // Every other file name is in the list.
+    let entries: Vec<String> = (0..entry_count)
+        .map(|i| format!("IMG_{:08}.JPG", i * 2))
+        .collect();
+    let names: Vec<String> = (0..name_count)
+        .map(|i| format!("IMG_{:08}.JPG", i % (entry_count * 2)))
+        .collect();
+    println!("{} list entries, {} file names", entries.len(), names.len());
+
+    let sample = &names[..BASELINE_SAMPLE.min(names.len())];
+    let baseline = time(sample, |name| entries.contains(name));
+    report("Vec::contains", baseline, sample.len());
+
+    let matcher = Matcher::new(&entries).expect("Cannot build matcher.");
+    report(
+        "Matcher, exact",
+        time(&names, |name| matcher.is_match(name)),
+        names.len(),
+    );
+
+    let options = MatchOptions {
+        ignore_case: true,
+        normalization: Normalization::Nfc,
+        ..MatchOptions::default()
+    };
+    let matcher = Matcher::with_options(&entries, options).expect("Cannot build matcher.");
+    report(
+        "Matcher, normalized",
+        time(&names, |name| matcher.is_match(name)),
+        names.len(),
+    );
+
+    let mut mixed = entries.clone();
+    mixed.extend(["*.RAW".to_string(), "re:^DSC_\\d{4}\\.NEF$".to_string()]);
+    let matcher = Matcher::new(&mixed).expect("Cannot build matcher.");
+    report(
+        "Matcher, with patterns",
+        time(&names, |name| matcher.is_match(name)),
+        names.len(),
+    );
+}
+
+/// Times `lookup` over every name, returning the total duration.
+fn time<F: Fn(&String) -> bool>(names: &[String], lookup: F) -> Duration {
+    let started = Instant::now();
+    let mut hits = 0;
+    for name in names {
+        if black_box(lookup(black_box(name))) {
+            hits += 1;
+        }
+    }
+    black_box(hits);
+    started.elapsed()
+}
+
+fn report(label: &str, elapsed: Duration, lookups: usize) {
+    let per_lookup = elapsed.as_nanos() as f64 / lookups.max(1) as f64;
+    println!(
+        "{:<24} {:>12.0} ns/lookup over {} lookups",
+        label, per_lookup, lookups
+    );
+}
@@ -0,0 +1,193 @@
+//! Errors reported by the stages of a run.
+
+use crate::matcher::PatternError;
+use crate::plan::PlanError;
+use crate::undo::UndoError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong during a run.
+#[derive(Debug)]
+pub enum Error {
+    /// The file list does not exist.
+    ListNotFound(PathBuf),
+    /// The source directory does not exist.
+    SourceNotFound(PathBuf),
+    /// The target directory does not exist.
+    TargetNotFound(PathBuf),
+    /// The target directory already holds files.
+    TargetNotEmpty(PathBuf),
+    /// A file already exists where a copy would go.
+    TargetExists(PathBuf),
+    /// A source file changed since the plan was made.
+    SourceChanged(PathBuf),
+    /// A new journal would replace the journal of an earlier run.
+    JournalExists(PathBuf),
+    /// A file list could not be read, such as a structured list with a
+    /// malformed row.
+    InvalidList {
+        /// The file list.
+        path: PathBuf,
+        /// What is wrong with it.
+        error: io::Error,
+    },
+    /// A list entry is not a valid pattern.
+    Pattern(PatternError),
+    /// The list could not be matched against the source.
+    Plan(PlanError),
+    /// A file put in place by an earlier run cannot be reverted.
+    Undo(UndoError),
+    /// A source file does not have the size or hash the file list expects.
+    ListMismatch {
+        /// Full path of the source file.
+        source: PathBuf,
+        /// What the file list expects, such as "12 bytes".
+        expected: String,
+        /// What the file has instead.
+        found: String,
+    },
+    /// A copy does not have the same contents as its source.
+    ChecksumMismatch {
+        /// Full path of the source file.
+        source: PathBuf,
+        /// Full path of the copy.
+        target: PathBuf,
+    },
+    /// Putting a file in place failed.
+    Transfer {
+        /// Full path of the source file.
+        source: PathBuf,
+        /// Full path the file was being put in place at.
+        target: PathBuf,
+        /// What went wrong.
+        error: io::Error,
+    },
+    /// Reading or writing a file failed.
+    Io {
+        /// The file being read or written.
+        path: PathBuf,
+        /// What went wrong.
+        error: io::Error,
+    },
+}
+
+impl Error {
+    /// Wraps an I/O error with the path it happened on.
+    pub fn io<P: AsRef<Path>>(path: P, error: io::Error) -> Error {
+        Error::Io {
+            path: path.as_ref().to_path_buf(),
+            error,
+        }
+    }
+
+    /// Whether the error comes from the arguments of the run, rather than
+    /// from something that went wrong while running.
+    pub fn is_usage(&self) -> bool {
+        matches!(
+            self,
+            Error::ListNotFound(_)
+                | Error::SourceNotFound(_)
+                | Error::TargetNotFound(_)
+                | Error::TargetNotEmpty(_)
+                | Error::JournalExists(_)
+                | Error::InvalidList { .. }
+                | Error::Pattern(_)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ListNotFound(path) => {
+                write!(f, "Path to file list `{}` does not exist", path.display())
+            }
+            Error::SourceNotFound(path) => {
+                write!(f, "Source path `{}` does not exist", path.display())
+            }
+            Error::TargetNotFound(path) => {
+                write!(f, "Target path `{}` does not exist", path.display())
+            }
+            Error::TargetNotEmpty(path) => {
+                write!(f, "Target path `{}` is not empty", path.display())
+            }
+            Error::TargetExists(path) => write!(f, "`{}` already exists", path.display()),
+            Error::SourceChanged(path) => {
+                write!(f, "`{}` changed since the plan was made", path.display())
+            }
+            Error::JournalExists(path) => write!(
+                f,
+                "Journal `{}` already exists, resume it or pick another path",
+                path.display()
+            ),
+            Error::InvalidList { path, error } => {
+                write!(f, "Invalid file list `{}`: {}", path.display(), error)
+            }
+            Error::Pattern(e) => e.fmt(f),
+            Error::Plan(e) => e.fmt(f),
+            Error::Undo(e) => e.fmt(f),
+            Error::ListMismatch {
+                source,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` does not match the file list, expected {} but found {}",
+                source.display(),
+                expected,
+                found
+            ),
+            Error::ChecksumMismatch { source, target } => write!(
+                f,
+                "Checksum of `{}` does not match `{}`",
+                target.display(),
+                source.display()
+            ),
+            Error::Transfer {
+                source,
+                target,
+                error,
+            } => write!(
+                f,
+                "Cannot copy `{}` to `{}`: {}",
+                source.display(),
+                target.display(),
+                error
+            ),
+            Error::Io { path, error } => write!(f, "`{}`: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Pattern(e) => Some(e),
+            Error::Plan(e) => Some(e),
+            Error::Undo(e) => Some(e),
+            Error::InvalidList { error, .. }
+            | Error::Transfer { error, .. }
+            | Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<PatternError> for Error {
+    fn from(e: PatternError) -> Error {
+        Error::Pattern(e)
+    }
+}
+
+impl From<PlanError> for Error {
+    fn from(e: PlanError) -> Error {
+        Error::Plan(e)
+    }
+}
+
+impl From<UndoError> for Error {
+    fn from(e: UndoError) -> Error {
+        Error::Undo(e)
+    }
+}
@@ -0,0 +1,575 @@
+//! Carrying out a plan.
+
+use crate::error::Error;
+use crate::existing::{resolve_existing, Action, ExistingPolicy};
+use crate::journal::Journal;
+use crate::plan::{Plan, PlannedCopy};
+use crate::plan_file::check_source;
+use crate::preserve::{Attribute, Unpreserved};
+use crate::transfer::{find_temp_files, transfer_checked, transfer_preserving, TransferMode};
+use crate::verify::{hash_file, HashAlgorithm};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Options that change how a plan is carried out.
+///
+/// The default options make a dry run, as the command line does, so
+/// `dry_run` has to be turned off for anything to be written.
+#[derive(Debug, Clone)]
+pub struct ExecuteOptions {
+    /// Only report what would be done, without writing anything. On by
+    /// default.
+    pub dry_run: bool,
+    /// Hash every file put in place with this algorithm.
+    pub hash: Option<HashAlgorithm>,
+    /// Compare the hash of every target with the hash of its source. Needs
+    /// `hash` to be set.
+    pub verify: bool,
+    /// How many more times a copy is attempted when its hash does not match
+    /// the source.
+    pub retries: u32,
+    /// Carry on with the rest of the plan when a copy fails.
+    pub keep_going: bool,
+    /// Number of files handled at the same time. `0` uses one per CPU.
+    pub jobs: usize,
+    /// What to do when a file already exists where a copy would go.
+    pub existing: ExistingPolicy,
+    /// Attributes carried over from each source to its copy or reflink.
+    pub preserve: Vec<Attribute>,
+    /// Refuse to put a file in place if its source changed since the plan
+    /// was made.
+    pub check_sources: bool,
+    /// Journal to record progress in. Files completed by the earlier runs it
+    /// records are checked and kept, and files they left half written are
+    /// replaced whatever `existing` says. The plan is adjusted with
+    /// [`Journal::apply_to`] and recorded before any file is handled.
+    pub journal: Option<Arc<Journal>>,
+}
+
+impl Default for ExecuteOptions {
+    fn default() -> ExecuteOptions {
+        ExecuteOptions {
+            dry_run: true,
+            hash: None,
+            verify: false,
+            retries: 0,
+            keep_going: false,
+            jobs: 0,
+            existing: ExistingPolicy::default(),
+            preserve: Vec::new(),
+            check_sources: false,
+            journal: None,
+        }
+    }
+}
+
+/// A file that was put in place, or left alone because it already was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transferred {
+    /// Full path of the file in the target directory. Differs from the
+    /// planned target when the file was renamed.
+    pub target: PathBuf,
+    /// Hex digest of the target, when hashing was enabled and the file was
+    /// put in place.
+    pub hash: Option<String>,
+    /// What was done to put the file in place.
+    pub action: Action,
+    /// Attributes that could not be carried over from the source.
+    pub unpreserved: Vec<Unpreserved>,
+}
+
+/// What happened to one of the copies in a plan.
+#[derive(Debug)]
+pub struct Outcome {
+    /// Position of the copy in [`Plan::copies`].
+    pub index: usize,
+    /// The file that was put in place, or why it could not be.
+    pub result: Result<Transferred, Error>,
+    /// How long the copy took.
+    pub duration: Duration,
+}
+
+/// What a call to [`execute_plan`] did.
+#[derive(Debug, Default)]
+pub struct Execution {
+    /// What happened to each copy that was attempted, in plan order.
+    pub outcomes: Vec<Outcome>,
+    /// Temporary files left by an interrupted run that were removed from the
+    /// target directory before any copy started, sorted by path.
+    pub removed: Vec<PathBuf>,
+}
+
+/// Copies, moves or links every file in the plan, as set by its mode.
+///
+/// Up to `jobs` files are handled at the same time. `on_copy` is called with
+/// each file and its outcome in plan order, as soon as it and every file
+/// before it have been handled, so progress reads the same however many jobs
+/// are used. Outcomes are returned in plan order too.
+///
+/// Missing directories below the target directory are created, and the
+/// temporary files left in it by an interrupted run, as found by
+/// [`find_temp_files`], are removed first and returned. Unless `keep_going`
+/// is set, no new file is started once one has failed, and files not started
+/// have no outcome. When `dry_run` is set nothing is read from or written to
+/// the target directory, nothing is returned and `on_copy` is still called
+/// for every file, without an outcome.
+///
+/// When resuming through a journal, entries whose files an earlier run put
+/// in place are counted as matched in `plan`.
+///
+/// Fails only when the plan cannot be recorded in the journal or a leftover
+/// temporary file cannot be removed. Files that fail have their error in
+/// their outcome.
+pub fn execute_plan<F>(
+    plan: &mut Plan,
+    options: &ExecuteOptions,
+    mut on_copy: F,
+) -> Result<Execution, Error>
+where
+    F: FnMut(&PlannedCopy, Option<&Outcome>),
+{
+    if let Some(journal) = &options.journal {
+        journal.apply_to(plan);
+        if !options.dry_run {
+            journal.record_plan(plan)?;
+        }
+    }
+    let plan = &*plan;
+    if options.dry_run {
+        for copy in &plan.copies {
+            on_copy(copy, None);
+        }
+        return Ok(Execution::default());
+    }
+
+    let target_dir = &plan.target_dir;
+    let removed = match find_temp_files(target_dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        found => found.map_err(|e| Error::io(target_dir, e))?,
+    };
+    for temp in &removed {
+        fs::remove_file(temp).map_err(|e| Error::io(temp, e))?;
+    }
+
+    let jobs = match options.jobs {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        jobs => jobs,
+    };
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let reserved = Mutex::new(plan.copies.iter().map(|c| c.target.clone()).collect());
+    let (sender, receiver) = mpsc::channel();
+
+    let mut outcomes = Vec::new();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(plan.copies.len()) {
+            let sender = sender.clone();
+            let (next, stop, reserved) = (&next, &stop, &reserved);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(copy) = plan.copies.get(index) else {
+                    return;
+                };
+                if stop.load(Ordering::Relaxed) {
+                    let _ = sender.send((index, None));
+                    continue;
+                }
+                let started = Instant::now();
+                let result = execute_copy(copy, options, reserved);
+                if result.is_err() && !options.keep_going {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                let outcome = Outcome {
+                    index,
+                    result,
+                    duration: started.elapsed(),
+                };
+                let _ = sender.send((index, Some(outcome)));
+            });
+        }
+        drop(sender);
+
+        // Workers finish out of order, so hold each outcome back until every
+        // file before it is done.
+        let mut pending: Vec<Option<Option<Outcome>>> = plan.copies.iter().map(|_| None).collect();
+        let mut reported = 0;
+        for (index, outcome) in receiver {
+            pending[index] = Some(outcome);
+            while let Some(done) = pending.get_mut(reported).and_then(Option::take) {
+                if let Some(outcome) = done {
+                    on_copy(&plan.copies[reported], Some(&outcome));
+                    outcomes.push(outcome);
+                }
+                reported += 1;
+            }
+        }
+    });
+    Ok(Execution { outcomes, removed })
+}
+
+/// Puts a single file in place, unless it already is, and records it in the
+/// journal. `reserved` holds the targets no renamed copy may take.
+fn execute_copy(
+    copy: &PlannedCopy,
+    options: &ExecuteOptions,
+    reserved: &Mutex<HashSet<PathBuf>>,
+) -> Result<Transferred, Error> {
+    let journal = options.journal.as_deref();
+    if let Some(journal) = journal {
+        if let Some(done) = journal.check_completed(copy)? {
+            let hash = match options.hash {
+                Some(algorithm) if algorithm == done.algorithm => Some(done.hash.clone()),
+                Some(algorithm) => Some(
+                    hash_file(algorithm, &done.target).map_err(|e| Error::io(&done.target, e))?,
+                ),
+                None => None,
+            };
+            return Ok(Transferred {
+                target: done.target.clone(),
+                hash,
+                action: Action::Resumed,
+                unpreserved: Vec::new(),
+            });
+        }
+    }
+
+    if options.check_sources {
+        check_source(copy)?;
+    }
+    check_expected(copy)?;
+    if let Some(parent) = copy.target.parent() {
+        let created = create_dirs(parent).map_err(|e| Error::io(parent, e))?;
+        if let Some(journal) = journal {
+            journal.record_created_dirs(&created)?;
+        }
+    }
+    let policy = match journal {
+        Some(journal) if journal.was_started(&copy.target) => ExistingPolicy::Overwrite,
+        _ => options.existing,
+    };
+    let (action, target) = resolve_existing(copy, policy, reserved)?;
+    if action == Action::Skipped {
+        return Ok(Transferred {
+            target,
+            hash: None,
+            action,
+            unpreserved: Vec::new(),
+        });
+    }
+    if let Some(journal) = journal {
+        journal.record_started(copy, &target)?;
+    }
+    let transferred = put_in_place(copy, options, action, target)?;
+    if let Some(journal) = journal {
+        let hash = match &transferred.hash {
+            Some(hash) if options.hash == Some(journal.algorithm()) => hash.clone(),
+            _ => hash_file(journal.algorithm(), &transferred.target)
+                .map_err(|e| Error::io(&transferred.target, e))?,
+        };
+        journal.record_completed(copy, &transferred, hash)?;
+    }
+    Ok(transferred)
+}
+
+/// Creates `dir` and every missing folder above it, like
+/// [`fs::create_dir_all`], returning the folders this call created,
+/// outermost first. Folders created by another job in the meantime are left
+/// out.
+fn create_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut missing: Vec<&Path> = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && !d.is_dir())
+        .collect();
+    missing.reverse();
+    let mut created = Vec::new();
+    for dir in missing {
+        match fs::create_dir(dir) {
+            Ok(()) => created.push(dir.to_path_buf()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(created)
+}
+
+/// Checks that the source of `copy` has the size and hash the file list
+/// expects, if any.
+fn check_expected(copy: &PlannedCopy) -> Result<(), Error> {
+    let source = &copy.source;
+    let mismatch = |expected, found| Error::ListMismatch {
+        source: source.clone(),
+        expected,
+        found,
+    };
+    if let Some(size) = copy.expected_size {
+        let found = fs::metadata(source)
+            .map_err(|e| Error::io(source, e))?
+            .len();
+        if found != size {
+            return Err(mismatch(
+                format!("{} bytes", size),
+                format!("{} bytes", found),
+            ));
+        }
+    }
+    if let Some(checksum) = &copy.expected_checksum {
+        let found = hash_file(checksum.algorithm, source).map_err(|e| Error::io(source, e))?;
+        if found != checksum.digest {
+            return Err(mismatch(
+                format!("hash {}", checksum.digest),
+                format!("hash {}", found),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Transfers a single file to `target`, hashing and verifying it if asked to.
+fn put_in_place(
+    copy: &PlannedCopy,
+    options: &ExecuteOptions,
+    action: Action,
+    target: PathBuf,
+) -> Result<Transferred, Error> {
+    let Some(algorithm) = options.hash else {
+        let unpreserved = transfer_preserving(copy.mode, &copy.source, &target, &options.preserve)
+            .map_err(|error| Error::Transfer {
+                source: copy.source.clone(),
+                target: target.clone(),
+                error,
+            })?;
+        return Ok(Transferred {
+            target,
+            hash: None,
+            action,
+            unpreserved,
+        });
+    };
+
+    // The source has to be hashed first, as a move takes it away.
+    let expected = if options.verify {
+        Some(hash_file(algorithm, &copy.source).map_err(|e| Error::io(&copy.source, e))?)
+    } else {
+        None
+    };
+
+    // A move can't be retried once the source is gone.
+    let retries = match copy.mode {
+        TransferMode::Move => 0,
+        _ => options.retries,
+    };
+    let (hash, unpreserved) = transfer_verified(
+        copy,
+        &target,
+        &options.preserve,
+        algorithm,
+        expected.as_deref(),
+        retries,
+    )?;
+    Ok(Transferred {
+        target,
+        hash: Some(hash),
+        action,
+        unpreserved,
+    })
+}
+
+/// Transfers `copy` to `target`, hashing the file written with `algorithm`
+/// before it takes the place of `target`, and trying again up to `retries`
+/// more times while the hash is not `expected`. A copy that never matches
+/// is not left at `target`. Returns the hash and the attributes that could
+/// not be carried over.
+fn transfer_verified(
+    copy: &PlannedCopy,
+    target: &Path,
+    attributes: &[Attribute],
+    algorithm: HashAlgorithm,
+    expected: Option<&str>,
+    retries: u32,
+) -> Result<(String, Vec<Unpreserved>), Error> {
+    for _ in 0..=retries {
+        let mut hash = String::new();
+        let checked = transfer_checked(copy.mode, &copy.source, target, attributes, |written| {
+            hash = hash_file(algorithm, written)?;
+            Ok(expected.is_none_or(|expected| expected == hash))
+        })
+        .map_err(|error| Error::Transfer {
+            source: copy.source.clone(),
+            target: target.to_path_buf(),
+            error,
+        })?;
+        if let Some(unpreserved) = checked {
+            return Ok((hash, unpreserved));
+        }
+    }
+    Err(Error::ChecksumMismatch {
+        source: copy.source.clone(),
+        target: target.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{planned_copy, TempDir};
+
+    #[test]
+    fn leftover_temp_files_are_removed() {
+        let root = TempDir::new("execute-temp");
+        let source = root.write("src/x.txt", "hello");
+        root.write("tgt/a/.finder-tmp-1-0", "half");
+
+        let mut plan = Plan {
+            target_dir: root.join("tgt"),
+            copies: vec![planned_copy(&source, root.join("tgt/x.txt"))],
+            ..Plan::default()
+        };
+        let options = ExecuteOptions {
+            dry_run: false,
+            ..ExecuteOptions::default()
+        };
+        let execution = execute_plan(&mut plan, &options, |_, _| {}).unwrap();
+        assert!(execution.outcomes[0].result.is_ok());
+        assert_eq!(execution.removed, [root.join("tgt/a/.finder-tmp-1-0")]);
+        assert!(!root.join("tgt/a/.finder-tmp-1-0").exists());
+        assert_eq!(fs::read_to_string(root.join("tgt/x.txt")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn verified_copies_carry_the_hash_of_their_source() {
+        let root = TempDir::new("execute-verify");
+        let source = root.write("src/x.txt", "hello");
+
+        let mut plan = Plan {
+            target_dir: root.join("tgt"),
+            copies: vec![planned_copy(&source, root.join("tgt/x.txt"))],
+            ..Plan::default()
+        };
+        let options = ExecuteOptions {
+            dry_run: false,
+            hash: Some(HashAlgorithm::Sha256),
+            verify: true,
+            retries: 2,
+            ..ExecuteOptions::default()
+        };
+        let execution = execute_plan(&mut plan, &options, |_, _| {}).unwrap();
+        let transferred = execution.outcomes[0].result.as_ref().unwrap();
+        let expected = hash_file(HashAlgorithm::Sha256, &source).unwrap();
+        assert_eq!(transferred.hash.as_deref(), Some(expected.as_str()));
+    }
+
+    #[test]
+    fn copies_that_never_match_are_not_put_in_place() {
+        let root = TempDir::new("execute-mismatch");
+        let source = root.write("src/x.txt", "new");
+        let target = root.write("tgt/x.txt", "old");
+        let verify = |copy: &PlannedCopy, retries| {
+            let result = transfer_verified(
+                copy,
+                &copy.target,
+                &[],
+                HashAlgorithm::Sha256,
+                Some("0"),
+                retries,
+            );
+            assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+        };
+
+        verify(&planned_copy(&source, &target), 2);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+
+        for mode in [TransferMode::Copy, TransferMode::Hardlink] {
+            let mut copy = planned_copy(&source, root.join("tgt/y.txt"));
+            copy.mode = mode;
+            verify(&copy, 2);
+            assert!(!copy.target.exists());
+        }
+        assert!(find_temp_files(&root.join("tgt")).unwrap().is_empty());
+        assert_eq!(fs::read_to_string(&source).unwrap(), "new");
+    }
+
+    #[test]
+    fn outcomes_come_in_plan_order_whatever_the_jobs() {
+        let root = TempDir::new("execute-jobs");
+        let copies: Vec<PlannedCopy> = (0..32)
+            .map(|i| {
+                let source = root.write(&format!("src/{}.txt", i), &"x".repeat(i * 1000));
+                planned_copy(source, root.join(format!("tgt/{}.txt", i)))
+            })
+            .collect();
+
+        let mut plan = Plan {
+            target_dir: root.join("tgt"),
+            copies: copies.clone(),
+            ..Plan::default()
+        };
+        let options = ExecuteOptions {
+            dry_run: false,
+            jobs: 4,
+            ..ExecuteOptions::default()
+        };
+        let mut reported = Vec::new();
+        let execution = execute_plan(&mut plan, &options, |copy, outcome| {
+            reported.push((copy.target.clone(), outcome.map(|o| o.index)));
+        })
+        .unwrap();
+
+        let expected: Vec<_> = copies
+            .iter()
+            .enumerate()
+            .map(|(i, copy)| (copy.target.clone(), Some(i)))
+            .collect();
+        assert_eq!(reported, expected);
+        let indices: Vec<usize> = execution.outcomes.iter().map(|o| o.index).collect();
+        assert_eq!(indices, (0..copies.len()).collect::<Vec<_>>());
+        assert!(execution.outcomes.iter().all(|o| o.result.is_ok()));
+    }
+
+    #[test]
+    fn no_file_starts_after_a_failure_unless_keeping_going() {
+        let root = TempDir::new("execute-keep-going");
+        let copies: Vec<PlannedCopy> = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|name| {
+                let source = root.join(format!("src/{}.txt", name));
+                if name != "b" {
+                    root.write(&format!("src/{}.txt", name), name);
+                }
+                planned_copy(source, root.join(format!("tgt/{}.txt", name)))
+            })
+            .collect();
+
+        for keep_going in [false, true] {
+            let _ = fs::remove_dir_all(root.join("tgt"));
+            let mut plan = Plan {
+                target_dir: root.join("tgt"),
+                copies: copies.clone(),
+                ..Plan::default()
+            };
+            let options = ExecuteOptions {
+                dry_run: false,
+                jobs: 1,
+                keep_going,
+                ..ExecuteOptions::default()
+            };
+            let execution = execute_plan(&mut plan, &options, |_, _| {}).unwrap();
+            let results: Vec<(usize, bool)> = execution
+                .outcomes
+                .iter()
+                .map(|o| (o.index, o.result.is_ok()))
+                .collect();
+            if keep_going {
+                assert_eq!(results, [(0, true), (1, false), (2, true), (3, true)]);
+            } else {
+                assert_eq!(results, [(0, true), (1, false)]);
+            }
+            assert_eq!(root.join("tgt/c.txt").exists(), keep_going);
+        }
+    }
+}
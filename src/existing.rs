@@ -0,0 +1,398 @@
+//! Deciding what to do when a target file already exists.
+
+use crate::error::Error;
+use crate::plan::{numbered_name, PlannedCopy};
+use crate::transfer::TransferMode;
+use crate::verify::{hash_file, HashAlgorithm};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What to do when a file already exists where a copy would go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ExistingPolicy {
+    /// Fail that file.
+    #[default]
+    Error,
+    /// Leave the existing file alone.
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+    /// Replace the existing file if the source was modified more recently.
+    UpdateIfNewer,
+    /// Replace the existing file if its size or contents differ from the
+    /// source.
+    UpdateIfDifferent,
+    /// Put the copy next to the existing file, adding `-1`, `-2`, ... to its
+    /// name.
+    Rename,
+}
+
+/// What was done to put a file in place.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Nothing was in the way.
+    #[default]
+    Created,
+    /// An existing file was replaced.
+    Overwritten,
+    /// An existing, out of date file was replaced.
+    Updated,
+    /// The file was put in place under another name.
+    Renamed,
+    /// An existing file was left alone.
+    Skipped,
+    /// The file was put in place by an earlier, interrupted run, and still
+    /// matches the journal.
+    Resumed,
+}
+
+/// Works out what to do for `copy` under `policy`, and where the file should
+/// go. Any existing file that is to be replaced by a link is removed first,
+/// unless it is the source itself, which is skipped.
+///
+/// `reserved` holds the targets other copies of the run are planned to take
+/// or were given when renamed, so that a renamed copy never picks one of
+/// them. A renamed copy's new target is added to it.
+pub(crate) fn resolve_existing(
+    copy: &PlannedCopy,
+    policy: ExistingPolicy,
+    reserved: &Mutex<HashSet<PathBuf>>,
+) -> Result<(Action, PathBuf), Error> {
+    let target = &copy.target;
+    let existing = match fs::symlink_metadata(target) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok((Action::Created, target.clone()))
+        }
+        Err(e) => return Err(Error::io(target, e)),
+    };
+
+    let action = match policy {
+        ExistingPolicy::Error => return Err(Error::TargetExists(target.clone())),
+        ExistingPolicy::Skip => Action::Skipped,
+        ExistingPolicy::Overwrite => Action::Overwritten,
+        ExistingPolicy::UpdateIfNewer => {
+            let newer = match (copy_modified(copy)?, existing.modified().ok()) {
+                (Some(source), Some(target)) => source > target,
+                _ => true,
+            };
+            if newer {
+                Action::Updated
+            } else {
+                Action::Skipped
+            }
+        }
+        ExistingPolicy::UpdateIfDifferent => {
+            if differs(&copy.source, target, existing.len())? {
+                Action::Updated
+            } else {
+                Action::Skipped
+            }
+        }
+        ExistingPolicy::Rename => return Ok((Action::Renamed, free_name(target, reserved)?)),
+    };
+
+    // Copies and moves are renamed over the existing file, which keeps it
+    // until the new one is complete, but links can't be created over it.
+    // When the existing file is the source itself, removing it would lose
+    // the file, and it is already in place anyway.
+    let renamed_over = matches!(
+        copy.mode,
+        TransferMode::Copy | TransferMode::Move | TransferMode::Reflink
+    );
+    if matches!(action, Action::Overwritten | Action::Updated) && !renamed_over {
+        if is_source(&copy.source, target, &existing)? {
+            return Ok((Action::Skipped, target.clone()));
+        }
+        fs::remove_file(target).map_err(|e| Error::io(target, e))?;
+    }
+    Ok((action, target.clone()))
+}
+
+/// Whether `target`, whose own metadata is `existing`, is the file at
+/// `source` or the file a link at `source` points to.
+#[cfg(unix)]
+fn is_source(source: &Path, _target: &Path, existing: &fs::Metadata) -> Result<bool, Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let same = |metadata: fs::Metadata| {
+        metadata.dev() == existing.dev() && metadata.ino() == existing.ino()
+    };
+    let linked = fs::symlink_metadata(source).map_err(|e| Error::io(source, e))?;
+    if same(linked) {
+        return Ok(true);
+    }
+    match fs::metadata(source) {
+        Ok(metadata) => Ok(same(metadata)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(Error::io(source, e)),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_source(source: &Path, target: &Path, _existing: &fs::Metadata) -> Result<bool, Error> {
+    let canonical = |path: &Path| fs::canonicalize(path).map_err(|e| Error::io(path, e));
+    let parent = target.parent().unwrap_or(Path::new("."));
+    let target = match target.file_name() {
+        Some(name) => canonical(parent)?.join(name),
+        None => return Ok(false),
+    };
+    Ok(target == source || target == canonical(source)?)
+}
+
+/// Last modification time of the source, read again in case it changed
+/// since indexing.
+fn copy_modified(copy: &PlannedCopy) -> Result<Option<std::time::SystemTime>, Error> {
+    let metadata = fs::metadata(&copy.source).map_err(|e| Error::io(&copy.source, e))?;
+    Ok(metadata.modified().ok())
+}
+
+/// Whether `source` and `target` differ in size or contents.
+fn differs(source: &Path, target: &Path, target_size: u64) -> Result<bool, Error> {
+    let source_size = fs::metadata(source)
+        .map_err(|e| Error::io(source, e))?
+        .len();
+    if source_size != target_size {
+        return Ok(true);
+    }
+    let hash = |path: &Path| hash_file(HashAlgorithm::Blake3, path).map_err(|e| Error::io(path, e));
+    Ok(hash(source)? != hash(target)?)
+}
+
+/// Finds the first numbered variant of `target` that does not exist yet and
+/// is not `reserved`, and reserves it.
+fn free_name(target: &Path, reserved: &Mutex<HashSet<PathBuf>>) -> Result<PathBuf, Error> {
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Held until the name is reserved, so two jobs can't pick the same one.
+    let mut reserved = reserved.lock().unwrap_or_else(|e| e.into_inner());
+    let mut n = 1;
+    loop {
+        let candidate = target.with_file_name(numbered_name(&file_name, n));
+        if reserved.contains(&candidate) {
+            n += 1;
+            continue;
+        }
+        match fs::symlink_metadata(&candidate) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                reserved.insert(candidate.clone());
+                return Ok(candidate);
+            }
+            Err(e) => return Err(Error::io(&candidate, e)),
+            Ok(_) => n += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::{execute_plan, ExecuteOptions};
+    use crate::plan::Plan;
+    use crate::test_util::{planned_copy, TempDir};
+    use std::time::{Duration, SystemTime};
+
+    fn resolve(copy: &PlannedCopy, policy: ExistingPolicy) -> Result<(Action, PathBuf), Error> {
+        resolve_existing(copy, policy, &Mutex::new(HashSet::new()))
+    }
+
+    /// Runs a plan of `copies` into `target_dir` under `policy`, returning
+    /// the action taken for each copy.
+    fn run(target_dir: &Path, copies: Vec<PlannedCopy>, policy: ExistingPolicy) -> Vec<Action> {
+        let mut plan = Plan {
+            target_dir: target_dir.to_path_buf(),
+            copies,
+            ..Plan::default()
+        };
+        let options = ExecuteOptions {
+            dry_run: false,
+            jobs: 1,
+            existing: policy,
+            ..ExecuteOptions::default()
+        };
+        let execution = execute_plan(&mut plan, &options, |_, _| {}).unwrap();
+        execution
+            .outcomes
+            .into_iter()
+            .map(|outcome| outcome.result.unwrap().action)
+            .collect()
+    }
+
+    fn set_modified(path: &Path, secs: u64) {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn missing_targets_are_created() {
+        let root = TempDir::new("existing-created");
+        let source = root.write("src/x.txt", "new");
+        let copy = planned_copy(&source, root.join("tgt/x.txt"));
+        assert_eq!(
+            resolve(&copy, ExistingPolicy::Error).unwrap(),
+            (Action::Created, copy.target.clone())
+        );
+    }
+
+    #[test]
+    fn existing_targets_fail_by_default() {
+        let root = TempDir::new("existing-error");
+        let source = root.write("src/x.txt", "new");
+        let target = root.write("tgt/x.txt", "old");
+        let copy = planned_copy(&source, &target);
+        assert!(matches!(
+            resolve(&copy, ExistingPolicy::Error),
+            Err(Error::TargetExists(path)) if path == target
+        ));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+    }
+
+    #[test]
+    fn skipped_targets_are_kept() {
+        let root = TempDir::new("existing-skip");
+        let source = root.write("src/x.txt", "new");
+        let target = root.write("tgt/x.txt", "old");
+        let copies = vec![planned_copy(&source, &target)];
+        assert_eq!(
+            run(&root.join("tgt"), copies, ExistingPolicy::Skip),
+            [Action::Skipped]
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+    }
+
+    #[test]
+    fn overwritten_targets_are_replaced() {
+        let root = TempDir::new("existing-overwrite");
+        let source = root.write("src/x.txt", "new");
+        let target = root.write("tgt/x.txt", "old");
+        let copies = vec![planned_copy(&source, &target)];
+        assert_eq!(
+            run(&root.join("tgt"), copies, ExistingPolicy::Overwrite),
+            [Action::Overwritten]
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+    }
+
+    #[test]
+    fn targets_are_updated_if_the_source_is_newer() {
+        let root = TempDir::new("existing-newer");
+        let older = root.write("src/older.txt", "new");
+        let newer = root.write("src/newer.txt", "new");
+        let kept = root.write("tgt/older.txt", "old");
+        let updated = root.write("tgt/newer.txt", "old");
+        set_modified(&older, 1_000);
+        set_modified(&newer, 3_000);
+        set_modified(&kept, 2_000);
+        set_modified(&updated, 2_000);
+
+        let copies = vec![planned_copy(&older, &kept), planned_copy(&newer, &updated)];
+        assert_eq!(
+            run(&root.join("tgt"), copies, ExistingPolicy::UpdateIfNewer),
+            [Action::Skipped, Action::Updated]
+        );
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&updated).unwrap(), "new");
+    }
+
+    #[test]
+    fn targets_are_updated_if_they_differ() {
+        let root = TempDir::new("existing-different");
+        let same = root.write("src/same.txt", "abc");
+        let resized = root.write("src/resized.txt", "abcd");
+        let changed = root.write("src/changed.txt", "abc");
+        root.write("tgt/same.txt", "abc");
+        root.write("tgt/resized.txt", "abc");
+        root.write("tgt/changed.txt", "xyz");
+
+        let copies = vec![
+            planned_copy(&same, root.join("tgt/same.txt")),
+            planned_copy(&resized, root.join("tgt/resized.txt")),
+            planned_copy(&changed, root.join("tgt/changed.txt")),
+        ];
+        assert_eq!(
+            run(&root.join("tgt"), copies, ExistingPolicy::UpdateIfDifferent),
+            [Action::Skipped, Action::Updated, Action::Updated]
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("tgt/resized.txt")).unwrap(),
+            "abcd"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("tgt/changed.txt")).unwrap(),
+            "abc"
+        );
+    }
+
+    #[test]
+    fn targets_replaced_by_links_are_removed_first() {
+        let root = TempDir::new("existing-link");
+        let source = root.write("src/x.txt", "new");
+        let target = root.write("tgt/x.txt", "old");
+        let mut copy = planned_copy(&source, &target);
+        copy.mode = TransferMode::Hardlink;
+
+        assert_eq!(
+            resolve(&copy, ExistingPolicy::Overwrite).unwrap(),
+            (Action::Overwritten, target.clone())
+        );
+        assert!(!target.exists());
+
+        root.write("tgt/x.txt", "old");
+        assert_eq!(
+            run(&root.join("tgt"), vec![copy], ExistingPolicy::Overwrite),
+            [Action::Overwritten]
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&source).unwrap(), "new");
+    }
+
+    #[test]
+    fn links_over_their_own_source_are_skipped() {
+        let root = TempDir::new("existing-same");
+        let source = root.write("d/a.txt", "hello");
+        for mode in [
+            TransferMode::Hardlink,
+            TransferMode::Symlink,
+            TransferMode::SymlinkRelative,
+        ] {
+            let mut copy = planned_copy(&source, &source);
+            copy.mode = mode;
+            for policy in [ExistingPolicy::Overwrite, ExistingPolicy::UpdateIfNewer] {
+                assert_eq!(
+                    resolve(&copy, policy).unwrap(),
+                    (Action::Skipped, source.clone())
+                );
+            }
+            assert_eq!(
+                run(&root.join("d"), vec![copy], ExistingPolicy::Overwrite),
+                [Action::Skipped]
+            );
+            assert_eq!(fs::read_to_string(&source).unwrap(), "hello");
+        }
+    }
+
+    #[test]
+    fn free_name_skips_reserved_names() {
+        let root = TempDir::new("existing-free-name");
+        let target = root.write("x.txt", "old");
+
+        let reserved = Mutex::new(HashSet::from([target.clone(), root.join("x-1.txt")]));
+        let first = free_name(&target, &reserved).unwrap();
+        let second = free_name(&target, &reserved).unwrap();
+        assert_eq!(first, root.join("x-2.txt"));
+        assert_eq!(second, root.join("x-3.txt"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Remembering the hashes of source files between runs.
+
+use crate::error::Error;
+use crate::verify::HashAlgorithm;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// One line of a hash cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedHash {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    algorithm: HashAlgorithm,
+    digest: String,
+}
+
+/// Hashes of files, kept in a file with one JSON object per line. A hash is
+/// only used while the file keeps the size and modification time it had
+/// when it was hashed.
+#[derive(Debug)]
+pub struct HashCache {
+    path: PathBuf,
+    hashes: Mutex<HashMap<PathBuf, CachedHash>>,
+    changed: AtomicBool,
+}
+
+impl HashCache {
+    /// Reads the cache at `path`, starting an empty one if it does not
+    /// exist. Lines that cannot be read are dropped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<HashCache, Error> {
+        let path = path.as_ref();
+        let mut hashes = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line.map_err(|e| Error::io(path, e))?;
+                    if let Ok(cached) = serde_json::from_str::<CachedHash>(&line) {
+                        hashes.insert(cached.path.clone(), cached);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::io(path, e)),
+        }
+        Ok(HashCache {
+            path: path.to_path_buf(),
+            hashes: Mutex::new(hashes),
+            changed: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the hex digest of the file at `path` made with `algorithm`,
+    /// if it is cached and the file has not changed since.
+    pub fn get(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: SystemTime,
+        algorithm: HashAlgorithm,
+    ) -> Option<String> {
+        let hashes = self.hashes.lock().unwrap_or_else(|e| e.into_inner());
+        hashes
+            .get(path)
+            .filter(|c| c.size == size && c.modified == modified && c.algorithm == algorithm)
+            .map(|c| c.digest.clone())
+    }
+
+    /// Remembers the hex digest of the file at `path`.
+    pub fn insert(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: SystemTime,
+        algorithm: HashAlgorithm,
+        digest: String,
+    ) {
+        let cached = CachedHash {
+            path: path.to_path_buf(),
+            size,
+            modified,
+            algorithm,
+            digest,
+        };
+        self.hashes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_path_buf(), cached);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Writes the cache back to its file, if any hash was added.
+    pub fn save(&self) -> Result<(), Error> {
+        if !self.changed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let hashes = self.hashes.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sorted: Vec<_> = hashes.values().collect();
+        sorted.sort_by(|a, b| a.path.cmp(&b.path));
+        let write = || {
+            let mut writer = BufWriter::new(File::create(&self.path)?);
+            for cached in sorted {
+                serde_json::to_writer(&mut writer, cached)?;
+                writeln!(writer)?;
+            }
+            writer.flush()
+        };
+        write().map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::time::Duration;
+
+    #[test]
+    fn saved_hashes_are_read_back() {
+        let root = TempDir::new("hash-cache-reload");
+        let path = root.join("cache");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let cache = HashCache::open(&path).unwrap();
+        cache.insert(
+            Path::new("/src/a.txt"),
+            5,
+            modified,
+            HashAlgorithm::Sha256,
+            "aa".to_string(),
+        );
+        cache.save().unwrap();
+
+        let cache = HashCache::open(&path).unwrap();
+        let get = |size, modified, algorithm| {
+            cache.get(Path::new("/src/a.txt"), size, modified, algorithm)
+        };
+        assert_eq!(
+            get(5, modified, HashAlgorithm::Sha256),
+            Some("aa".to_string())
+        );
+        assert_eq!(get(6, modified, HashAlgorithm::Sha256), None);
+        assert_eq!(
+            get(5, modified + Duration::from_secs(1), HashAlgorithm::Sha256),
+            None
+        );
+        assert_eq!(get(5, modified, HashAlgorithm::Blake3), None);
+    }
+
+    #[test]
+    fn unchanged_caches_are_not_written_and_bad_lines_are_dropped() {
+        let root = TempDir::new("hash-cache-unchanged");
+        let path = root.join("cache");
+        HashCache::open(&path).unwrap().save().unwrap();
+        assert!(!path.exists());
+
+        std::fs::write(&path, "not json\n").unwrap();
+        let cache = HashCache::open(&path).unwrap();
+        assert_eq!(
+            cache.get(
+                Path::new("/src/a.txt"),
+                5,
+                SystemTime::UNIX_EPOCH,
+                HashAlgorithm::Sha256
+            ),
+            None
+        );
+    }
+}
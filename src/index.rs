@@ -0,0 +1,383 @@
+//! Indexing the files in the source directory.
+
+use crate::error::Error;
+use crate::hash_cache::HashCache;
+use crate::matcher::Matcher;
+use crate::verify::{hash_file, Checksum};
+use jwalk::{Parallelism, WalkDirGeneric};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A file found in the source directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// Full path of the file.
+    pub path: PathBuf,
+    /// Path of the file relative to the source directory.
+    pub relative_path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Last modification time, if the platform reports one.
+    pub modified: Option<SystemTime>,
+    /// Hash of the file, when it was matched by its contents.
+    pub checksum: Option<Checksum>,
+}
+
+/// Two or more files in the source directory that share a file name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision<'a> {
+    /// The shared file name.
+    pub file_name: &'a str,
+    /// Every file with that name, in the order they were found.
+    pub files: &'a [SourceFile],
+}
+
+/// Every wanted file found below a source directory, keyed by file name.
+#[derive(Debug, Clone, Default)]
+pub struct SourceIndex {
+    root: PathBuf,
+    files: BTreeMap<String, Vec<SourceFile>>,
+    unmatched: BTreeMap<String, Vec<SourceFile>>,
+    stopped_early: bool,
+}
+
+impl SourceIndex {
+    /// The directory that was indexed.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Looks up every file with the given name.
+    pub fn get(&self, file_name: &str) -> &[SourceFile] {
+        self.files.get(file_name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Iterates over file names and the files that have them, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[SourceFile])> {
+        self.files
+            .iter()
+            .map(|(name, files)| (name.as_str(), files.as_slice()))
+    }
+
+    /// Looks up every file with the given name that no list entry matched,
+    /// when they were kept.
+    pub fn get_unmatched(&self, file_name: &str) -> &[SourceFile] {
+        self.unmatched.get(file_name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Iterates over the names of the files that no list entry matched, and
+    /// the files that have them, sorted by name. Only filled in when
+    /// [`IndexOptions::keep_unmatched`] is set.
+    pub fn unmatched(&self) -> impl Iterator<Item = (&str, &[SourceFile])> {
+        self.unmatched
+            .iter()
+            .map(|(name, files)| (name.as_str(), files.as_slice()))
+    }
+
+    /// Iterates over every file name that is shared by more than one file.
+    pub fn collisions(&self) -> impl Iterator<Item = Collision<'_>> {
+        self.iter()
+            .filter(|(_, files)| files.len() > 1)
+            .map(|(file_name, files)| Collision { file_name, files })
+    }
+
+    /// Whether the walk stopped before reading the whole source directory,
+    /// because every list entry had been found.
+    pub fn stopped_early(&self) -> bool {
+        self.stopped_early
+    }
+
+    /// Number of distinct file names.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether no files were indexed.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// Options that change how the source directory is walked.
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    /// Number of directories read at the same time. `0` uses one thread per
+    /// CPU, and `1` reads every directory on the calling thread.
+    pub jobs: usize,
+    /// Stop walking as soon as every list entry has matched a file.
+    ///
+    /// Only lists made of plain file names can stop early, as a pattern may
+    /// always match one more file. Files found later that share a name with
+    /// one already found are then not seen, so collisions can go unnoticed.
+    pub stop_when_found: bool,
+    /// Where to look up and remember the hashes of files, when the matcher
+    /// finds files by their contents.
+    pub hash_cache: Option<Arc<HashCache>>,
+    /// Also keep the files that no list entry matched, apart from the others,
+    /// so names close to unmatched entries can be suggested. Costs memory
+    /// for every file in the source directory.
+    pub keep_unmatched: bool,
+}
+
+/// What is learned about a file while its directory is read.
+#[derive(Debug, Clone, Default)]
+struct FileState {
+    metadata: Option<Metadata>,
+    checksum: Option<Checksum>,
+    wanted: bool,
+}
+
+/// Walks `source_dir` recursively and indexes every file, other than a
+/// directory, that is matched by `matcher`, by its name or its path.
+///
+/// Files are checked while each directory is read, so only the wanted files
+/// are kept in memory. When the matcher finds files by their contents, every
+/// file of a size it wants is hashed then, unless the hash cache has it.
+/// Directories are read in file name order, so files that
+/// share a name are always listed in the same order, however many jobs are
+/// used.
+pub fn index_source<P: AsRef<Path>>(
+    source_dir: P,
+    matcher: &Matcher,
+    options: &IndexOptions,
+) -> Result<SourceIndex, Error> {
+    let source_dir = source_dir.as_ref();
+    let root = std::path::absolute(source_dir).map_err(|e| Error::io(source_dir, e))?;
+    if !root.exists() {
+        return Err(Error::SourceNotFound(root));
+    }
+
+    let parallelism = match options.jobs {
+        1 => Parallelism::Serial,
+        jobs => Parallelism::RayonNewPool(jobs),
+    };
+    // Drop unwanted files and read the metadata of wanted ones while each
+    // directory is read, so that it also happens in parallel. So does the
+    // hashing of files that are matched by their contents.
+    let wanted = matcher.clone();
+    let walk_root = root.clone();
+    let cache = options.hash_cache.clone();
+    let by_contents = matcher.digest_algorithm().is_some();
+    let keep_unmatched = options.keep_unmatched;
+    let walk = WalkDirGeneric::<((), FileState)>::new(&root)
+        .sort(true)
+        .skip_hidden(false)
+        .parallelism(parallelism)
+        .process_read_dir(move |_, dir, _, children| {
+            let relative_dir = dir.strip_prefix(&walk_root).unwrap_or(dir);
+            children.retain_mut(|child| match child {
+                Ok(child) if child.file_type.is_dir() => true,
+                Ok(child) => {
+                    child.client_state.wanted =
+                        by_contents || wanted.is_match_at(&relative_dir.join(&child.file_name));
+                    child.client_state.wanted || keep_unmatched
+                }
+                Err(_) => false,
+            });
+            for child in children.iter_mut().flatten() {
+                if !child.file_type.is_dir() {
+                    let metadata = child.metadata().ok();
+                    if child.client_state.wanted && by_contents {
+                        child.client_state.checksum = metadata.as_ref().and_then(|metadata| {
+                            match_contents(&child.path(), metadata, &wanted, cache.as_deref())
+                        });
+                        child.client_state.wanted = child.client_state.checksum.is_some();
+                    }
+                    child.client_state.metadata = metadata;
+                }
+            }
+            if by_contents && !keep_unmatched {
+                children.retain(|child| match child {
+                    Ok(child) => child.file_type.is_dir() || child.client_state.wanted,
+                    Err(_) => false,
+                });
+            }
+        });
+
+    let can_stop = options.stop_when_found && !matcher.has_patterns();
+    let mut found = HashSet::new();
+    let mut stopped_early = false;
+    let mut files: BTreeMap<String, Vec<SourceFile>> = BTreeMap::new();
+    let mut unmatched: BTreeMap<String, Vec<SourceFile>> = BTreeMap::new();
+    for entry in walk
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| !e.file_type().is_dir())
+    {
+        let file_name = String::from(entry.file_name().to_string_lossy());
+        let path = entry.path();
+        let relative_path = path.strip_prefix(&root).unwrap_or(&path).to_path_buf();
+        let FileState {
+            metadata,
+            checksum,
+            wanted,
+        } = entry.client_state;
+        let file = SourceFile {
+            relative_path,
+            size: metadata.as_ref().map_or(0, |m| m.len()),
+            modified: metadata.and_then(|m| m.modified().ok()),
+            checksum,
+            path,
+        };
+        if !wanted {
+            unmatched.entry(file_name).or_default().push(file);
+            continue;
+        }
+        if can_stop {
+            found.extend(matcher.matching_entries_for(&file));
+        }
+        files.entry(file_name).or_default().push(file);
+        if can_stop && found.len() == matcher.entries().len() {
+            stopped_early = true;
+            break;
+        }
+    }
+
+    Ok(SourceIndex {
+        root,
+        files,
+        unmatched,
+        stopped_early,
+    })
+}
+
+/// Hashes the file at `path` if `matcher` finds files by their contents and
+/// wants its size, returning the hash when the matcher lists it.
+fn match_contents(
+    path: &Path,
+    metadata: &Metadata,
+    matcher: &Matcher,
+    cache: Option<&HashCache>,
+) -> Option<Checksum> {
+    let algorithm = matcher.digest_algorithm()?;
+    let size = metadata.len();
+    if !matcher.wants_size(size) {
+        return None;
+    }
+    let modified = metadata.modified().ok();
+    let cached = cache
+        .zip(modified)
+        .and_then(|(cache, modified)| cache.get(path, size, modified, algorithm));
+    let digest = match cached {
+        Some(digest) => digest,
+        None => {
+            let digest = hash_file(algorithm, path).ok()?;
+            if let Some((cache, modified)) = cache.zip(modified) {
+                cache.insert(path, size, modified, algorithm, digest.clone());
+            }
+            digest
+        }
+    };
+    if matcher.matching_digest(&digest).is_empty() {
+        return None;
+    }
+    Some(Checksum { algorithm, digest })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::list::ListEntry;
+    use crate::matcher::MatchOptions;
+    use crate::test_util::TempDir;
+    use crate::verify::HashAlgorithm;
+
+    #[test]
+    fn only_files_of_listed_sizes_are_hashed() {
+        let root = TempDir::new("index-sizes");
+        let wanted = root.write("src/a.txt", "hello");
+        let other = root.write("src/b.txt", "hello!");
+        let digest = hash_file(HashAlgorithm::Sha256, &wanted).unwrap();
+        let entries = [ListEntry {
+            name: digest.clone(),
+            size: Some(5),
+            ..ListEntry::default()
+        }];
+        let matcher = Matcher::with_digests(&entries, HashAlgorithm::Sha256).unwrap();
+        let cache = Arc::new(HashCache::open(root.join("cache")).unwrap());
+        let options = IndexOptions {
+            jobs: 1,
+            hash_cache: Some(cache.clone()),
+            ..IndexOptions::default()
+        };
+        let index = index_source(root.join("src"), &matcher, &options).unwrap();
+
+        let files = index.get("a.txt");
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].checksum.as_ref().unwrap().digest, digest);
+        assert!(index.get("b.txt").is_empty());
+
+        let cached = |path: &Path| {
+            let metadata = std::fs::metadata(path).unwrap();
+            let modified = metadata.modified().unwrap();
+            cache.get(path, metadata.len(), modified, HashAlgorithm::Sha256)
+        };
+        assert_eq!(cached(&wanted), Some(digest));
+        assert_eq!(cached(&other), None);
+    }
+
+    #[test]
+    fn collisions_are_listed_in_the_same_order_whatever_the_jobs() {
+        let root = TempDir::new("index-jobs");
+        let mut expected = Vec::new();
+        for dir in ["a", "a/b", "c", "d/e/f", "g", "h", "i", "j"] {
+            expected.push(PathBuf::from(dir).join("x.txt"));
+            root.write(&format!("src/{}/x.txt", dir), dir);
+            root.write(&format!("src/{}/y.txt", dir), dir);
+        }
+        expected.sort();
+        let matcher = Matcher::new(&["x.txt", "y.txt"]).unwrap();
+
+        let collisions = |jobs| {
+            let options = IndexOptions {
+                jobs,
+                ..IndexOptions::default()
+            };
+            let index = index_source(root.join("src"), &matcher, &options).unwrap();
+            index
+                .collisions()
+                .map(|c| {
+                    let paths: Vec<PathBuf> =
+                        c.files.iter().map(|f| f.relative_path.clone()).collect();
+                    (c.file_name.to_string(), paths)
+                })
+                .collect::<Vec<_>>()
+        };
+        let serial = collisions(1);
+        assert_eq!(serial.len(), 2);
+        assert_eq!(serial[0], ("x.txt".to_string(), expected));
+        for _ in 0..5 {
+            assert_eq!(collisions(4), serial);
+        }
+    }
+
+    #[test]
+    fn walks_stop_early_only_for_plain_names() {
+        let root = TempDir::new("index-stop");
+        for name in ["a.txt", "b.txt", "c.md", "d.txt"] {
+            root.write(&format!("src/{}", name), name);
+        }
+        let stopped = |entries: &[&str], stems| {
+            let options = MatchOptions {
+                stems,
+                ..MatchOptions::default()
+            };
+            let matcher = Matcher::with_options(entries, options).unwrap();
+            let options = IndexOptions {
+                jobs: 1,
+                stop_when_found: true,
+                ..IndexOptions::default()
+            };
+            let index = index_source(root.join("src"), &matcher, &options).unwrap();
+            (index.stopped_early(), index.len())
+        };
+
+        assert_eq!(stopped(&["a.txt"], false), (true, 1));
+        assert_eq!(stopped(&["d.txt", "a.txt"], false), (true, 2));
+        assert_eq!(stopped(&["a.txt", "*.md"], false), (false, 2));
+        assert_eq!(stopped(&["a.txt"], true), (false, 1));
+    }
+}
@@ -0,0 +1,423 @@
+//! Recording the progress of a run, so an interrupted run can be resumed.
+
+use crate::error::Error;
+use crate::execute::Transferred;
+use crate::existing::Action;
+use crate::plan::{Plan, PlannedCopy};
+use crate::transfer::TransferMode;
+use crate::verify::{hash_file, HashAlgorithm};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One line of a journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Line {
+    /// A run is starting.
+    Run { target_dir: PathBuf },
+    /// A file the run is about to put in place.
+    Planned {
+        source: PathBuf,
+        target: PathBuf,
+        size: u64,
+    },
+    /// A folder the run created to put a file in.
+    CreatedDir { path: PathBuf },
+    /// A file the run has started writing.
+    Started { source: PathBuf, target: PathBuf },
+    /// A file the run has put in place.
+    Completed(Completed),
+}
+
+/// A file that a run has put in place.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Completed {
+    /// Full path of the source file.
+    pub source: PathBuf,
+    /// Full path the file was put in place at.
+    pub target: PathBuf,
+    /// The list entry that matched the file.
+    pub entry: String,
+    /// How the file was put in place.
+    #[serde(default)]
+    pub mode: TransferMode,
+    /// What was done to put the file in place.
+    #[serde(default)]
+    pub action: Action,
+    /// Size of the target in bytes.
+    pub size: u64,
+    /// Hash function used for `hash`.
+    pub algorithm: HashAlgorithm,
+    /// Hex digest of the target.
+    pub hash: String,
+}
+
+/// Everything recorded in a journal, by one or more runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    /// Target directories of the runs, in the order they ran.
+    pub target_dirs: Vec<PathBuf>,
+    /// Folders the runs created to put files in, which did not exist before.
+    pub created_dirs: HashSet<PathBuf>,
+    /// Targets the runs started writing and did not complete afterwards, so
+    /// they may hold a partial copy.
+    pub started: HashSet<PathBuf>,
+    /// Files the runs put in place, in the order they were completed. A file
+    /// put in place again by a later run is only listed at its last
+    /// completion.
+    pub completed: Vec<Completed>,
+}
+
+/// Reads every line of the journal at `path`.
+pub fn read_history<P: AsRef<Path>>(path: P) -> Result<History, Error> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path).map_err(|e| Error::io(path, e))?);
+    let lines: Vec<String> = reader
+        .lines()
+        .collect::<io::Result<_>>()
+        .map_err(|e| Error::io(path, e))?;
+
+    let mut history = History::default();
+    for (number, text) in lines.iter().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(text) {
+            Ok(Line::Run { target_dir }) => history.target_dirs.push(target_dir),
+            Ok(Line::Planned { .. }) => {}
+            Ok(Line::CreatedDir { path }) => {
+                history.created_dirs.insert(path);
+            }
+            Ok(Line::Started { target, .. }) => {
+                history.started.insert(target);
+            }
+            Ok(Line::Completed(done)) => {
+                history.started.remove(&done.target);
+                history.completed.push(done);
+            }
+            // The run may have been stopped halfway through its last line.
+            Err(_) if number + 1 == lines.len() => {}
+            Err(e) => return Err(Error::io(path, e.into())),
+        }
+    }
+
+    // Keep the last completion of every target.
+    let mut seen = HashSet::new();
+    history.completed.reverse();
+    history
+        .completed
+        .retain(|done| seen.insert(done.target.clone()));
+    history.completed.reverse();
+    Ok(history)
+}
+
+/// A state file with one JSON object per line, recording the files a run
+/// plans to put in place, the folders it creates, each file as it starts
+/// being written and each file once it is in place.
+///
+/// Lines are written as soon as they are known, so the journal stays useful
+/// however the run ends. A resumed run appends to the journal of the runs
+/// before it.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    algorithm: HashAlgorithm,
+    /// Targets that earlier runs started writing and did not complete.
+    started: HashSet<PathBuf>,
+    /// Files put in place by earlier runs, by source.
+    completed: HashMap<PathBuf, Completed>,
+}
+
+impl Journal {
+    /// Starts a new journal at `path`. Completed files are hashed with
+    /// `algorithm`.
+    ///
+    /// Fails if a file is already there, as it may be the only record of an
+    /// earlier run that [`check_undo`] can revert.
+    ///
+    /// [`check_undo`]: crate::undo::check_undo
+    pub fn create<P: AsRef<Path>>(path: P, algorithm: HashAlgorithm) -> Result<Journal, Error> {
+        let path = path.as_ref();
+        let file = File::create_new(path).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => Error::JournalExists(path.to_path_buf()),
+            _ => Error::io(path, e),
+        })?;
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            algorithm,
+            started: HashSet::new(),
+            completed: HashMap::new(),
+        })
+    }
+
+    /// Reads the journal of an interrupted run at `path`, and carries on
+    /// writing to it. Newly completed files are hashed with `algorithm`.
+    pub fn resume<P: AsRef<Path>>(path: P, algorithm: HashAlgorithm) -> Result<Journal, Error> {
+        let path = path.as_ref();
+        let history = read_history(path)?;
+        let completed = history
+            .completed
+            .into_iter()
+            .map(|done| (done.source.clone(), done))
+            .collect();
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| Error::io(path, e))?;
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            algorithm,
+            started: history.started,
+            completed,
+        })
+    }
+
+    /// Hash function used for newly completed files.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// List entries that matched a file completed by an earlier run.
+    pub fn completed_entries(&self) -> HashSet<&str> {
+        self.completed.values().map(|c| c.entry.as_str()).collect()
+    }
+
+    /// Counts the entries of `plan` that matched nothing but matched a file
+    /// completed by an earlier run, which may have moved it away, as matched.
+    /// Returns those entries, in list order.
+    pub fn apply_to(&self, plan: &mut Plan) -> Vec<String> {
+        let completed = self.completed_entries();
+        let (done, unmatched): (Vec<String>, Vec<String>) = plan
+            .unmatched
+            .drain(..)
+            .partition(|entry| completed.contains(entry.as_str()));
+        plan.unmatched = unmatched;
+        plan.matched.extend(done.iter().cloned());
+        done
+    }
+
+    /// Records the start of a run, and every copy in `plan` as planned.
+    pub fn record_plan(&self, plan: &Plan) -> Result<(), Error> {
+        self.write(&Line::Run {
+            target_dir: plan.target_dir.clone(),
+        })?;
+        for copy in &plan.copies {
+            self.write(&Line::Planned {
+                source: copy.source.clone(),
+                target: copy.target.clone(),
+                size: copy.size,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Records the folders that were created to put a file in.
+    pub(crate) fn record_created_dirs(&self, dirs: &[PathBuf]) -> Result<(), Error> {
+        for dir in dirs {
+            self.write(&Line::CreatedDir { path: dir.clone() })?;
+        }
+        Ok(())
+    }
+
+    /// Records that `copy` is about to be written to `target`.
+    pub(crate) fn record_started(&self, copy: &PlannedCopy, target: &Path) -> Result<(), Error> {
+        self.write(&Line::Started {
+            source: copy.source.clone(),
+            target: target.to_path_buf(),
+        })
+    }
+
+    /// Records that `copy` was put in place, with the given hex digest of
+    /// the target made with [`Journal::algorithm`].
+    pub(crate) fn record_completed(
+        &self,
+        copy: &PlannedCopy,
+        transferred: &Transferred,
+        hash: String,
+    ) -> Result<(), Error> {
+        let target = &transferred.target;
+        let size = fs::metadata(target)
+            .map_err(|e| Error::io(target, e))?
+            .len();
+        self.write(&Line::Completed(Completed {
+            source: copy.source.clone(),
+            target: target.clone(),
+            entry: copy.entry.clone(),
+            mode: copy.mode,
+            action: transferred.action,
+            size,
+            algorithm: self.algorithm,
+            hash,
+        }))
+    }
+
+    /// Whether an earlier run started writing a file at `target` and never
+    /// completed it, so whatever is there now may be a partial copy of its
+    /// own.
+    pub(crate) fn was_started(&self, target: &Path) -> bool {
+        self.started.contains(target)
+    }
+
+    /// Looks up `copy` among the files completed by earlier runs, returning
+    /// the record if the target still has the recorded size and hash.
+    ///
+    /// The target may have been renamed, but must be in the same directory
+    /// as planned.
+    pub(crate) fn check_completed(&self, copy: &PlannedCopy) -> Result<Option<&Completed>, Error> {
+        let Some(done) = self.completed.get(&copy.source) else {
+            return Ok(None);
+        };
+        if done.target.parent() != copy.target.parent() {
+            return Ok(None);
+        }
+        match fs::metadata(&done.target) {
+            Ok(metadata) if metadata.len() == done.size => {}
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::io(&done.target, e)),
+        }
+        let hash =
+            hash_file(done.algorithm, &done.target).map_err(|e| Error::io(&done.target, e))?;
+        Ok((hash == done.hash).then_some(done))
+    }
+
+    /// Appends a line, in a single write so that lines from several jobs
+    /// don't mix.
+    fn write(&self, line: &Line) -> Result<(), Error> {
+        let mut text = serde_json::to_string(line).map_err(|e| Error::io(&self.path, e.into()))?;
+        text.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(text.as_bytes())
+            .map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::execute_plan;
+    use crate::test_util::{execute_journaled, journaled, planned_copy, TempDir};
+
+    /// A new directory for the test called `name`, holding `src/x.txt`.
+    fn setup(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("journal-{}", name));
+        root.write("src/x.txt", "hello");
+        fs::create_dir_all(root.join("tgt")).unwrap();
+        root
+    }
+
+    fn plan(root: &Path) -> Plan {
+        Plan {
+            target_dir: root.join("tgt"),
+            copies: vec![planned_copy(root.join("src/x.txt"), root.join("tgt/x.txt"))],
+            matched: vec!["x.txt".to_string()],
+            ..Plan::default()
+        }
+    }
+
+    /// Runs `plan` resuming the journal at `path`, returning the outcome of
+    /// its only file.
+    fn resume(path: &Path, plan: &mut Plan) -> Result<Transferred, Error> {
+        let journal = Journal::resume(path, HashAlgorithm::Sha256).unwrap();
+        let mut execution = execute_plan(plan, &journaled(journal), |_, _| {}).unwrap();
+        execution.outcomes.remove(0).result
+    }
+
+    #[test]
+    fn history_ignores_a_truncated_last_line() {
+        let root = setup("truncated");
+        let path = root.join("journal");
+        let started = format!(
+            "{{\"event\":\"started\",\"source\":\"/a\",\"target\":{:?}}}\n",
+            root.join("tgt/x.txt")
+        );
+        fs::write(&path, format!("{}{{\"event\":\"comp", started)).unwrap();
+        let history = read_history(&path).unwrap();
+        assert!(history.started.contains(&root.join("tgt/x.txt")));
+        assert!(history.completed.is_empty());
+
+        fs::write(&path, format!("{{\"event\":\"comp\n{}", started)).unwrap();
+        assert!(read_history(&path).is_err());
+    }
+
+    #[test]
+    fn create_refuses_an_existing_journal() {
+        let root = setup("exists");
+        let path = root.join("journal");
+        let mut plan = plan(&root);
+        execute_journaled(&mut plan, &path);
+
+        let error = Journal::create(&path, HashAlgorithm::Sha256).unwrap_err();
+        assert!(matches!(error, Error::JournalExists(_)), "{}", error);
+        assert_eq!(read_history(&path).unwrap().completed.len(), 1);
+    }
+
+    #[test]
+    fn resume_keeps_completed_files() {
+        let root = setup("completed");
+        let path = root.join("journal");
+        let mut plan = plan(&root);
+        execute_journaled(&mut plan, &path);
+
+        let transferred = resume(&path, &mut plan).unwrap();
+        assert_eq!(transferred.action, Action::Resumed);
+    }
+
+    #[test]
+    fn resume_does_not_overwrite_edited_files() {
+        let root = setup("edited");
+        let path = root.join("journal");
+        let mut plan = plan(&root);
+        execute_journaled(&mut plan, &path);
+        fs::write(root.join("tgt/x.txt"), "zz").unwrap();
+
+        let error = resume(&path, &mut plan).unwrap_err();
+        assert!(matches!(error, Error::TargetExists(_)), "{}", error);
+        assert_eq!(fs::read_to_string(root.join("tgt/x.txt")).unwrap(), "zz");
+    }
+
+    #[test]
+    fn resume_replaces_half_written_files() {
+        let root = setup("half-written");
+        let path = root.join("journal");
+        let mut plan = plan(&root);
+        let journal = Journal::create(&path, HashAlgorithm::Sha256).unwrap();
+        journal
+            .record_started(&plan.copies[0], &root.join("tgt/x.txt"))
+            .unwrap();
+        drop(journal);
+        fs::write(root.join("tgt/x.txt"), "hel").unwrap();
+
+        let transferred = resume(&path, &mut plan).unwrap();
+        assert_eq!(transferred.action, Action::Overwritten);
+        assert_eq!(fs::read_to_string(root.join("tgt/x.txt")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn entries_of_moved_files_count_as_matched() {
+        let root = setup("moved");
+        let path = root.join("journal");
+        let mut plan = plan(&root);
+        plan.copies[0].mode = TransferMode::Move;
+        execute_journaled(&mut plan, &path);
+
+        // The source is gone, so a new plan no longer matches it.
+        let mut plan = Plan {
+            target_dir: root.join("tgt"),
+            unmatched: vec!["x.txt".to_string(), "y.txt".to_string()],
+            ..Plan::default()
+        };
+        let journal = Journal::resume(&path, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(journal.apply_to(&mut plan), ["x.txt"]);
+        assert_eq!(plan.matched, ["x.txt"]);
+        assert_eq!(plan.unmatched, ["y.txt"]);
+    }
+}
@@ -0,0 +1,84 @@
+//! Finder copies files from a list of file names to a destination directory.
+//!
+//! A run is split into four stages that can be called on their own:
+//!
+//! 1. [`load_list`] reads the names of the files to look for, and a
+//!    [`Matcher`] is built from them.
+//! 2. [`index_source`] walks the source directory and indexes every file
+//!    the matcher wants.
+//! 3. [`plan_matches`] works out which list entry matched each indexed file
+//!    and where it will be copied to.
+//! 4. [`execute_plan`] copies, moves or links the files, or only reports them
+//!    on a dry run, optionally recording its progress in a [`Journal`].
+//!
+//! A plan can be saved with [`save_plan`], reviewed, and applied later by
+//! loading it with [`load_plan`].
+//!
+//! The files a run recorded in its journal can later be reverted with
+//! [`check_undo`] and [`undo_file`].
+//!
+//! ```no_run
+//! use finder::{ExecuteOptions, IndexOptions, Matcher, PlanOptions};
+//! use std::path::Path;
+//!
+//! let file_names = finder::load_list("files.txt")?;
+//! let matcher = Matcher::new(&file_names)?;
+//! let index = finder::index_source("/mnt/a", &matcher, &IndexOptions::default())?;
+//! let options = PlanOptions::default();
+//! let mut plan = finder::plan_matches(&matcher, &index, Path::new("/mnt/b"), &options)?;
+//! // The default options make a dry run. Set `dry_run: false` to copy for real.
+//! let execution = finder::execute_plan(&mut plan, &ExecuteOptions::default(), |copy, _| {
+//!     println!("Would copy `{}`", copy.source.display());
+//! })?;
+//! for outcome in execution.outcomes {
+//!     outcome.result?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod error;
+pub mod execute;
+pub mod existing;
+pub mod hash_cache;
+pub mod index;
+pub mod journal;
+pub mod list;
+pub mod matcher;
+pub mod plan;
+pub mod plan_file;
+pub mod preserve;
+pub mod report;
+pub mod suggest;
+#[cfg(test)]
+mod test_util;
+pub mod transfer;
+pub mod undo;
+pub mod verify;
+
+pub use error::Error;
+pub use execute::{execute_plan, ExecuteOptions, Execution, Outcome, Transferred};
+pub use existing::{Action, ExistingPolicy};
+pub use hash_cache::HashCache;
+pub use index::{index_source, Collision, IndexOptions, SourceFile, SourceIndex};
+pub use journal::{read_history, Completed, History, Journal};
+pub use list::{
+    load_list, load_lists, read_entries, read_list, read_list_with, save_list, save_list_with,
+    ListEntry, ListFormat, ListOptions, STDIN,
+};
+pub use matcher::{MatchOptions, Matcher, Normalization, PatternError};
+pub use plan::{
+    check_target, plan_matches, CollisionPolicy, Plan, PlanError, PlanOptions, PlannedCopy,
+    SkipReason, SkippedFile,
+};
+pub use plan_file::{hash_sources, load_plan, save_plan, PlanFile};
+pub use preserve::{preserve, Attribute, Unpreserved};
+pub use report::{
+    build_records, collision_records, shared_target_records, write_report, Record, RecordKind,
+    ReportFormat, Status,
+};
+pub use suggest::{accept_suggestions, suggest, SuggestOptions, Suggestion};
+pub use transfer::{
+    find_temp_files, transfer, transfer_checked, transfer_preserving, TransferMode, TEMP_PREFIX,
+};
+pub use undo::{check_undo, undo_file, Revert, UndoError};
+pub use verify::{hash_file, write_manifest, Checksum, HashAlgorithm};
@@ -0,0 +1,500 @@
+//! Loading and saving lists of file names.
+
+use crate::error::Error;
+use crate::verify::{Checksum, HashAlgorithm};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Component, Path};
+
+/// Path that stands for stdin in [`load_lists`].
+pub const STDIN: &str = "-";
+
+/// How the entries of a file list are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ListFormat {
+    /// One file name per line. Blank lines and lines starting with `#` are
+    /// skipped, and whitespace around names is removed.
+    #[default]
+    Plain,
+    /// Comma-separated values with a header row naming the `name`, `target`,
+    /// `size` and `hash` columns. Only `name` is required.
+    Csv,
+    /// Like `csv`, separated by tabs.
+    Tsv,
+    /// A JSON array of objects with `name`, `target`, `size` and `hash`
+    /// fields, or of bare file names.
+    Json,
+    /// One JSON object or file name per line.
+    Ndjson,
+}
+
+impl ListFormat {
+    /// Guesses the format of the list at `path` from its extension, falling
+    /// back to [`ListFormat::Plain`].
+    pub fn from_path(path: &Path) -> ListFormat {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        match extension.to_ascii_lowercase().as_str() {
+            "csv" => ListFormat::Csv,
+            "tsv" | "tab" => ListFormat::Tsv,
+            "json" => ListFormat::Json,
+            "ndjson" | "jsonl" => ListFormat::Ndjson,
+            _ => ListFormat::Plain,
+        }
+    }
+}
+
+/// Options that change how file lists are read.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    /// Entries are separated by NUL bytes instead of line breaks, so they
+    /// may contain line breaks themselves. Only used by the plain format.
+    pub nul_separated: bool,
+    /// Format of every list. When not set, it is guessed from the extension
+    /// of each list, and lists read from stdin are plain.
+    pub format: Option<ListFormat>,
+    /// Hash function of the digests in a `hash` field that have no
+    /// `sha256:` or `blake3:` prefix.
+    pub hash: HashAlgorithm,
+}
+
+/// An entry of a file list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListEntry {
+    /// File name, glob or regular expression to look for.
+    pub name: String,
+    /// Where matched files go, relative to the target directory: a new path
+    /// for the file, or a folder to put it in when it ends with `/`.
+    pub target: Option<String>,
+    /// Size the matched file is expected to have, in bytes.
+    pub size: Option<u64>,
+    /// Hash the matched file is expected to have.
+    pub checksum: Option<Checksum>,
+}
+
+impl From<String> for ListEntry {
+    fn from(name: String) -> ListEntry {
+        ListEntry {
+            name,
+            ..ListEntry::default()
+        }
+    }
+}
+
+/// An entry as written in a structured list, before it is checked.
+#[derive(Deserialize)]
+struct RawEntry {
+    #[serde(alias = "source")]
+    name: String,
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    hash: Option<String>,
+}
+
+/// A JSON entry, which may also be a bare file name.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonEntry {
+    Name(String),
+    Entry(RawEntry),
+}
+
+/// Reads a file list from disk, returning the names of its entries. The
+/// format is guessed from the extension of `path`.
+pub fn load_list<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Error> {
+    let entries = load_list_with(path.as_ref(), &ListOptions::default())?;
+    Ok(entries.into_iter().map(|entry| entry.name).collect())
+}
+
+/// Reads every file list in `paths` and joins their entries, in order. A
+/// path of [`STDIN`] reads the list from stdin.
+pub fn load_lists<P: AsRef<Path>>(
+    paths: &[P],
+    options: &ListOptions,
+) -> Result<Vec<ListEntry>, Error> {
+    let mut entries = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        if path == Path::new(STDIN) {
+            let stdin = io::stdin().lock();
+            let format = options.format.unwrap_or_default();
+            let read = read_entries(stdin, format, options);
+            entries.extend(read.map_err(|e| list_error("<stdin>", e))?);
+        } else {
+            entries.extend(load_list_with(path, options)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn load_list_with(path: &Path, options: &ListOptions) -> Result<Vec<ListEntry>, Error> {
+    let file = File::open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => Error::ListNotFound(path.to_path_buf()),
+        _ => Error::io(path, e),
+    })?;
+    let format = options
+        .format
+        .unwrap_or_else(|| ListFormat::from_path(path));
+    read_entries(BufReader::new(file), format, options).map_err(|e| list_error(path, e))
+}
+
+/// Wraps an error met while reading the list at `path`, telling entries that
+/// are not valid apart from reads that failed.
+fn list_error<P: AsRef<Path>>(path: P, error: io::Error) -> Error {
+    match error.kind() {
+        io::ErrorKind::InvalidData => Error::InvalidList {
+            path: path.as_ref().to_path_buf(),
+            error,
+        },
+        _ => Error::io(path, error),
+    }
+}
+
+/// Reads a plain file list from any buffered reader, one file name per line.
+pub fn read_list<R: BufRead>(reader: R) -> io::Result<Vec<String>> {
+    read_list_with(reader, &ListOptions::default())
+}
+
+/// Reads a plain file list from any buffered reader, as set by `options`.
+///
+/// NUL-separated entries are kept as they are, except for empty ones. Those
+/// that are not valid UTF-8 have the invalid bytes replaced, just as file
+/// names are when the source directory is read.
+pub fn read_list_with<R: BufRead>(reader: R, options: &ListOptions) -> io::Result<Vec<String>> {
+    if !options.nul_separated {
+        let mut names = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let name = line.trim();
+            if !name.is_empty() && !name.starts_with('#') {
+                names.push(name.to_string());
+            }
+        }
+        return Ok(names);
+    }
+    let mut names = Vec::new();
+    for entry in reader.split(b'\0') {
+        let entry = entry?;
+        if !entry.is_empty() {
+            names.push(String::from_utf8_lossy(&entry).into_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// Reads a file list in `format` from any buffered reader.
+pub fn read_entries<R: BufRead>(
+    reader: R,
+    format: ListFormat,
+    options: &ListOptions,
+) -> io::Result<Vec<ListEntry>> {
+    match format {
+        ListFormat::Plain => {
+            let names = read_list_with(reader, options)?;
+            Ok(names.into_iter().map(ListEntry::from).collect())
+        }
+        ListFormat::Csv => read_delimited(reader, b',', options.hash),
+        ListFormat::Tsv => read_delimited(reader, b'\t', options.hash),
+        ListFormat::Json => {
+            let entries: Vec<JsonEntry> = serde_json::from_reader(reader)?;
+            entries
+                .into_iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    check_json_entry(entry, options.hash).map_err(|e| at("entry", i + 1, e))
+                })
+                .collect()
+        }
+        ListFormat::Ndjson => {
+            let mut entries = Vec::new();
+            for (i, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry = serde_json::from_str(&line)
+                    .map_err(io::Error::from)
+                    .and_then(|entry| check_json_entry(entry, options.hash))
+                    .map_err(|e| at("line", i + 1, e))?;
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+}
+
+/// Reads CSV or TSV rows with a header row, separated by `delimiter`.
+fn read_delimited<R: BufRead>(
+    reader: R,
+    delimiter: u8,
+    hash: HashAlgorithm,
+) -> io::Result<Vec<ListEntry>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(reader);
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let line = record.position().map_or(0, |p| p.line() as usize);
+        let raw: RawEntry = record.deserialize(Some(&headers)).map_err(csv_error)?;
+        entries.push(check_entry(raw, hash).map_err(|e| at("line", line, e))?);
+    }
+    Ok(entries)
+}
+
+/// Turns a CSV error into an I/O error, marking errors in the text itself
+/// as invalid data.
+fn csv_error(error: csv::Error) -> io::Error {
+    if error.is_io_error() {
+        return error.into();
+    }
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn check_json_entry(entry: JsonEntry, hash: HashAlgorithm) -> io::Result<ListEntry> {
+    match entry {
+        JsonEntry::Name(name) => check_entry(
+            RawEntry {
+                name,
+                target: None,
+                size: None,
+                hash: None,
+            },
+            hash,
+        ),
+        JsonEntry::Entry(raw) => check_entry(raw, hash),
+    }
+}
+
+/// Checks the fields of a structured entry, and parses its hash, taking a
+/// digest without a prefix to be made with `algorithm`.
+fn check_entry(raw: RawEntry, algorithm: HashAlgorithm) -> io::Result<ListEntry> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let name = raw.name.trim().to_string();
+    if name.is_empty() {
+        return Err(invalid("the name is empty".into()));
+    }
+    let target = raw
+        .target
+        .map(|target| target.trim().to_string())
+        .filter(|target| !target.is_empty());
+    if let Some(target) = &target {
+        let inside = Path::new(target)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            return Err(invalid(format!(
+                "target `{}` is not a relative path inside the target directory",
+                target
+            )));
+        }
+    }
+    let checksum = match raw.hash.as_deref().map(str::trim) {
+        Some("") | None => None,
+        Some(hash) => Some(parse_checksum(hash, algorithm).ok_or_else(|| {
+            invalid(format!(
+                "hash `{}` is not a SHA-256 or BLAKE3 hex digest",
+                hash
+            ))
+        })?),
+    };
+    Ok(ListEntry {
+        name,
+        target,
+        size: raw.size,
+        checksum,
+    })
+}
+
+/// Parses a hex digest, optionally prefixed with `sha256:` or `blake3:`.
+/// Digests without a prefix are taken to be made with `default`.
+pub(crate) fn parse_checksum(hash: &str, default: HashAlgorithm) -> Option<Checksum> {
+    let (algorithm, digest) = match hash.split_once(':') {
+        Some((prefix, digest)) => match prefix.to_ascii_lowercase().as_str() {
+            "sha256" => (HashAlgorithm::Sha256, digest),
+            "blake3" => (HashAlgorithm::Blake3, digest),
+            _ => return None,
+        },
+        None => (default, hash),
+    };
+    if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(Checksum {
+        algorithm,
+        digest: digest.to_ascii_lowercase(),
+    })
+}
+
+/// Adds the position of the entry an error was found in to its message.
+fn at(what: &str, n: usize, error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), format!("{} {}: {}", what, n, error))
+}
+
+/// Writes a plain file list to disk, one file name per line, in the format
+/// read by [`load_list`].
+///
+/// Names that contain line breaks, start with `#` or have whitespace around
+/// them don't read back the same. Use [`save_list_with`] to write them
+/// separated by NUL bytes instead.
+pub fn save_list<P: AsRef<Path>, S: AsRef<str>>(path: P, file_names: &[S]) -> Result<(), Error> {
+    save_list_with(path, file_names, &ListOptions::default())
+}
+
+/// Writes a plain file list to disk, as set by `options`. With
+/// `nul_separated` set, every name is followed by a NUL byte, so it reads
+/// back exactly as it is.
+pub fn save_list_with<P: AsRef<Path>, S: AsRef<str>>(
+    path: P,
+    file_names: &[S],
+    options: &ListOptions,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let separator = if options.nul_separated { b'\0' } else { b'\n' };
+    let write = || {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for file_name in file_names {
+            writer.write_all(file_name.as_ref().as_bytes())?;
+            writer.write_all(&[separator])?;
+        }
+        writer.flush()
+    };
+    write().map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn read(text: &str, format: ListFormat) -> io::Result<Vec<ListEntry>> {
+        read_entries(text.as_bytes(), format, &ListOptions::default())
+    }
+
+    fn error(text: &str, format: ListFormat) -> String {
+        read(text, format).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn plain_lists_skip_blank_lines_and_comments() {
+        let names = read_list("  a.txt \n\n# not a file\n\tb.txt\n".as_bytes()).unwrap();
+        assert_eq!(names, ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn nul_separated_entries_are_kept_as_they_are() {
+        let options = ListOptions {
+            nul_separated: true,
+            ..ListOptions::default()
+        };
+        let names = read_list_with(" a.txt\0\0# b\nc.txt\0".as_bytes(), &options).unwrap();
+        assert_eq!(names, [" a.txt", "# b\nc.txt"]);
+    }
+
+    #[test]
+    fn saved_lists_read_back_as_they_were_written() {
+        let root = TempDir::new("list-save");
+        let path = root.join("missing.txt");
+        save_list(&path, &["a.txt", "b c.txt"]).unwrap();
+        assert_eq!(load_list(&path).unwrap(), ["a.txt", "b c.txt"]);
+
+        let options = ListOptions {
+            nul_separated: true,
+            ..ListOptions::default()
+        };
+        let names = ["a\nb.txt", "# c.txt", " d.txt "];
+        save_list_with(&path, &names, &options).unwrap();
+        let read = read_list_with(BufReader::new(File::open(&path).unwrap()), &options);
+        assert_eq!(read.unwrap(), names);
+    }
+
+    #[test]
+    fn targets_must_stay_inside_the_target_directory() {
+        let message = error("name,target\na.txt,ok/\nb.txt,../b.txt\n", ListFormat::Csv);
+        assert!(
+            message.starts_with("line 3: target `../b.txt`"),
+            "{}",
+            message
+        );
+        let message = error("name,target\na.txt,/etc/a.txt\n", ListFormat::Csv);
+        assert!(
+            message.starts_with("line 2: target `/etc/a.txt`"),
+            "{}",
+            message
+        );
+
+        let entries = read("name,target\na.txt,./sub/a.txt\n", ListFormat::Csv).unwrap();
+        assert_eq!(entries[0].target.as_deref(), Some("./sub/a.txt"));
+    }
+
+    #[test]
+    fn digests_use_their_prefix_or_the_list_hash() {
+        let digest = "AB".repeat(32);
+        let options = ListOptions {
+            hash: HashAlgorithm::Blake3,
+            ..ListOptions::default()
+        };
+        let text = format!("name,hash\na.txt,{0}\nb.txt,sha256:{0}\n", digest);
+        let entries = read_entries(text.as_bytes(), ListFormat::Csv, &options).unwrap();
+        let checksums: Vec<_> = entries
+            .iter()
+            .map(|entry| entry.checksum.clone().unwrap())
+            .collect();
+        assert_eq!(checksums[0].algorithm, HashAlgorithm::Blake3);
+        assert_eq!(checksums[1].algorithm, HashAlgorithm::Sha256);
+        assert_eq!(checksums[0].digest, digest.to_ascii_lowercase());
+
+        let entries = read(&format!("name,hash\na.txt,{}\n", digest), ListFormat::Csv).unwrap();
+        let checksum = entries[0].checksum.as_ref().unwrap();
+        assert_eq!(checksum.algorithm, HashAlgorithm::Sha256);
+
+        let message = error("name,hash\na.txt,md5:abc\n", ListFormat::Csv);
+        assert!(message.starts_with("line 2: hash `md5:abc`"), "{}", message);
+    }
+
+    #[test]
+    fn errors_name_their_line_or_entry() {
+        let message = error("name,size\n# comment\na.txt,1\n,2\n", ListFormat::Csv);
+        assert_eq!(message, "line 4: the name is empty");
+        let message = error(r#"["a.txt", {"name": " "}]"#, ListFormat::Json);
+        assert_eq!(message, "entry 2: the name is empty");
+        let message = error("\"a.txt\"\n\n{\"name\": \"\"}\n", ListFormat::Ndjson);
+        assert_eq!(message, "line 3: the name is empty");
+    }
+
+    #[test]
+    fn invalid_rows_are_usage_errors() {
+        let root = TempDir::new("list-invalid");
+        let path = root.write("list.csv", "name,target,size\na.txt,../a.txt,1\n");
+        let error = load_lists(&[&path], &ListOptions::default()).unwrap_err();
+        assert!(matches!(error, Error::InvalidList { .. }), "{}", error);
+        assert!(error.is_usage());
+
+        let path = root.write("list.csv", "name,size\na.txt,large\n");
+        let error = load_lists(&[&path], &ListOptions::default()).unwrap_err();
+        assert!(error.is_usage(), "{}", error);
+        let path = root.write("list.json", "[\"a.txt\", 1]");
+        let error = load_lists(&[&path], &ListOptions::default()).unwrap_err();
+        assert!(error.is_usage(), "{}", error);
+    }
+
+    #[test]
+    fn json_lists_take_names_and_objects() {
+        let entries = read(
+            r#"["a.txt", {"name": "b.txt", "target": "x/", "size": 3}]"#,
+            ListFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(entries[0], ListEntry::from("a.txt".to_string()));
+        assert_eq!(entries[1].target.as_deref(), Some("x/"));
+        assert_eq!(entries[1].size, Some(3));
+    }
+}
@@ -1,7 +1,7 @@
 /// The program reads the file list, checks if the source directory and
-/// target directory exist, makes a list of every file in the source
-/// directory recursively, and then copies any file from the source
-/// to the target if the file is in the file list.
+/// target directory exist, searches the source directory recursively for
+/// the files in the file list, and then copies every file it found from the
+/// source to the target.
 ///
 /// By default, the program will print the files that would be copied.
 /// To copy the files for real, use the `--disable-dry-run` flag.
@@ -13,25 +13,106 @@
 ///
 /// Note: This program assumes that the file list, source directory, and target
 /// directory are valid and accessible. If any of these paths do not exist, the
-/// program will print an error message and exit.
+/// program will print an error message and exit with status 2. See the
+/// `EXIT_*` constants for the other exit statuses.
+///
+/// The `plan` subcommand writes what a run would do to a file, which the
+/// `apply` subcommand carries out later. The `undo` subcommand reverts the
+/// files put in place by a run made with `--journal`.
 ///
 /// More information can be found in the command line help message.
-use clap::Parser;
-use std::collections::HashMap;
-use std::fs;
+use clap::{Parser, Subcommand};
+use finder::{
+    Action, Attribute, CollisionPolicy, Error, ExecuteOptions, ExistingPolicy, HashAlgorithm,
+    HashCache, IndexOptions, Journal, ListFormat, ListOptions, MatchOptions, Matcher,
+    Normalization, Plan, PlanError, PlanFile, PlanOptions, PlannedCopy, Record, ReportFormat,
+    SuggestOptions, Suggestion, TransferMode,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufWriter, Write};
 use std::path;
-use std::path::Path;
-use walkdir::WalkDir;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Exit code when the run stopped before copying, such as when the report
+/// cannot be written or listed names collide.
+const EXIT_ERROR: u8 = 1;
+
+/// Exit code when the arguments are unusable, such as a missing file list,
+/// source or target directory, or an invalid pattern. Matches the code used
+/// by clap for invalid arguments.
+const EXIT_USAGE: u8 = 2;
+
+/// Exit code when some files were put in place and others failed.
+const EXIT_PARTIAL_FAILURE: u8 = 3;
+
+/// Exit code when every file that was attempted failed.
+const EXIT_TOTAL_FAILURE: u8 = 4;
+
+/// Set when the report is written to stdout, so progress messages go to
+/// stderr instead.
+static REPORT_ON_STDOUT: AtomicBool = AtomicBool::new(false);
+
+/// Prints a progress message to stdout, or to stderr when stdout is taken by
+/// the report.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if REPORT_ON_STDOUT.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
 
 /// Finder copies files from a list of file names to a destination directory.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
-struct Args {
-    /// Path to a file containing a list of file names to copy.
-    #[arg(short, long)]
-    file_list: String,
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    select: Option<SelectArgs>,
+
+    #[command(flatten)]
+    execute: ExecuteArgs,
+
+    /// Number of directories read and files copied at the same time. `0` uses one per CPU.
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the files a run would put in place to a plan file, without copying anything.
+    Plan(PlanArgs),
+    /// Carry out a plan file written by `plan`, refusing files whose source changed since.
+    Apply(ApplyArgs),
+    /// Revert the files put in place by an earlier run, as recorded in its journal.
+    Undo(UndoArgs),
+}
+
+/// Arguments that pick the files to copy and where they go.
+#[derive(clap::Args, Debug)]
+struct SelectArgs {
+    /// Path to a file containing a list of file names, globs or `re:` regular expressions to copy.
+    /// Use `-` to read the list from stdin. Can be repeated to join several lists.
+    #[arg(short, long, required = true)]
+    file_list: Vec<String>,
+
+    /// Format of the file lists. Guessed from their extension by default, with lists read from
+    /// stdin taken to be plain.
+    #[arg(long, value_enum)]
+    list_format: Option<ListFormat>,
+
+    /// Entries of the file list are separated by NUL bytes instead of line breaks, as written by
+    /// `find -print0`.
+    #[arg(short = '0', long, action)]
+    null: bool,
 
     /// Directory where the files are located.
     #[arg(short, long)]
@@ -41,107 +122,734 @@ struct Args {
     #[arg(short, long)]
     target_dir: String,
 
+    /// What to do when several files in the source directory share a listed name.
+    #[arg(short = 'c', long, value_enum, default_value_t = CollisionPolicy::Error)]
+    on_collision: CollisionPolicy,
+
+    /// Match file names without regard to case.
+    #[arg(short, long, action)]
+    ignore_case: bool,
+
+    /// Unicode normalization applied to list entries and file names before matching.
+    #[arg(short, long, value_enum, default_value_t = Normalization::None)]
+    normalize: Normalization,
+
+    /// Treat list entries as base names, also matching every file that adds extensions to them,
+    /// so `DSC_0042` finds `DSC_0042.NEF` and `DSC_0042.XMP`.
+    #[arg(long, action)]
+    stems: bool,
+
+    /// Only match files with one of these extensions when matching stems, separated by commas.
+    #[arg(long, value_delimiter = ',', requires = "stems")]
+    extensions: Vec<String>,
+
+    /// Treat list entries as digests of file contents made with this hash function, such as the
+    /// lines of a `sha256sum` manifest, and copy the files that match whatever their name.
+    #[arg(long, value_enum, conflicts_with = "stems")]
+    match_hash: Option<HashAlgorithm>,
+
+    /// Remember the hashes taken by `--match-hash` in this file, so files that have not changed
+    /// are not hashed again by later runs.
+    #[arg(long, requires = "match_hash")]
+    hash_cache: Option<String>,
+
+    /// Suggest the closest file names in the source directory for every list entry that matched
+    /// nothing. Keeps the name of every file in the source directory in memory, and compares
+    /// every missing entry with the names close to it in length, which can take a while when
+    /// many entries are missing from a large source directory.
+    #[arg(long, action, conflicts_with = "match_hash")]
+    suggest: bool,
+
+    /// Lowest similarity, from 0 to 1, for a file name to be suggested.
+    #[arg(long, value_parser = parse_score, default_value_t = 0.75)]
+    suggest_threshold: f64,
+
+    /// Copy the best suggestion for an entry as if the entry matched it, when it is at least this
+    /// similar, from 0 to 1, no other name is as close and only one file has the name. Implies
+    /// `--suggest`.
+    #[arg(long, value_parser = parse_score, conflicts_with = "match_hash")]
+    accept_suggestions: Option<f64>,
+
+    /// Recreate each file's path relative to the source directory under the target directory.
+    #[arg(short, long, action)]
+    preserve_structure: bool,
+
+    /// How each file is put in the target directory.
+    #[arg(long, value_enum, default_value_t = TransferMode::Copy)]
+    mode: TransferMode,
+
+    /// Stop reading the source directory once every listed name is found. Only works for
+    /// lists without patterns, and hides collisions with files found later.
+    #[arg(long, action)]
+    stop_when_found: bool,
+
+    /// Write the list entries that matched no file to this path, one per line, or separated by
+    /// NUL bytes with `--null`.
+    #[arg(short, long)]
+    missing_list: Option<String>,
+}
+
+/// Arguments that change how the files are put in place.
+#[derive(clap::Args, Debug)]
+struct ExecuteArgs {
     /// Disable dry run mode, copy files for real.
     #[arg(short, long, action)]
     disable_dry_run: bool,
+
+    /// Allow a non-empty target directory, and what to do when a file is already there.
+    #[arg(long, value_enum)]
+    existing: Option<ExistingPolicy>,
+
+    /// Attributes to carry over from each source file to its copy: `times`, `mode`, `owner`,
+    /// `xattr` and `acl`, separated by commas.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    preserve: Vec<Attribute>,
+
+    /// Carry over every attribute, like `--preserve times,mode,owner,xattr,acl`.
+    #[arg(short, long, action)]
+    archive: bool,
+
+    /// Hash every file after it is put in place and compare it with its source.
+    #[arg(long, action)]
+    verify: bool,
+
+    /// Hash function used by `--verify`, `--manifest` and `--journal`.
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Sha256)]
+    hash: HashAlgorithm,
+
+    /// How many more times to copy a file whose hash does not match its source.
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+
+    /// Write a `sha256sum`-compatible checksum manifest of the target files to this path.
+    #[arg(long)]
+    manifest: Option<String>,
+
+    /// Record planned and completed files in this state file, so an interrupted run can be resumed.
+    #[arg(long)]
+    journal: Option<String>,
+
+    /// Carry on from the journal of an interrupted run, checking the files it completed and
+    /// copying the rest. Allows a non-empty target directory.
+    #[arg(long, action, requires = "journal")]
+    resume: bool,
+
+    /// Write a report with one record per list entry and per matched file.
+    #[arg(short, long, value_enum)]
+    report: Option<ReportFormat>,
+
+    /// Where to write the report. Defaults to stdout, moving progress messages to stderr.
+    #[arg(long, requires = "report")]
+    report_file: Option<String>,
+
+    /// Carry on with the remaining files when one fails, instead of stopping.
+    #[arg(short, long, action)]
+    keep_going: bool,
+}
+
+impl ExecuteArgs {
+    /// Whether the target directory has to be empty.
+    fn require_empty(&self) -> bool {
+        self.existing.is_none() && !self.resume
+    }
 }
 
-fn main() {
+/// Arguments of the `plan` subcommand.
+#[derive(clap::Args, Debug)]
+struct PlanArgs {
+    #[command(flatten)]
+    select: SelectArgs,
+
+    /// Where to write the plan file.
+    #[arg(short, long)]
+    output: String,
+
+    /// Hash every source file with this function, so `apply` also refuses files whose contents
+    /// changed.
+    #[arg(long, value_enum)]
+    hash: Option<HashAlgorithm>,
+
+    /// Number of directories read at the same time. `0` uses one per CPU.
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+}
+
+/// Arguments of the `apply` subcommand.
+#[derive(clap::Args, Debug)]
+struct ApplyArgs {
+    /// Plan file written by `plan`.
+    plan: String,
+
+    #[command(flatten)]
+    execute: ExecuteArgs,
+
+    /// Number of files copied at the same time. `0` uses one per CPU.
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+}
+
+/// Arguments of the `undo` subcommand.
+#[derive(clap::Args, Debug)]
+struct UndoArgs {
+    /// Journal written by the run to undo with `--journal`.
+    journal: String,
+
+    /// Disable dry run mode, revert files for real.
+    #[arg(short, long, action)]
+    disable_dry_run: bool,
+}
+
+fn main() -> ExitCode {
     // Parse the command line arguments.
-    let args = Args::parse();
+    let cli = Cli::parse();
+    let result = match (cli.command, cli.select) {
+        (Some(Command::Plan(args)), _) => plan(args),
+        (Some(Command::Apply(args)), _) => {
+            report_on_stdout(&args.execute);
+            apply(args)
+        }
+        (Some(Command::Undo(args)), _) => undo(args),
+        (None, Some(select)) => {
+            report_on_stdout(&cli.execute);
+            run(&select, &cli.execute, cli.jobs)
+        }
+        (None, None) => unreachable!("clap requires the run arguments without a subcommand"),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            say!("ERROR: {}", e);
+            if e.is_usage() {
+                ExitCode::from(EXIT_USAGE)
+            } else {
+                ExitCode::from(EXIT_ERROR)
+            }
+        }
+    }
+}
 
-    // Get the path to the file list.
-    let file_name_list_path = args.file_list;
+/// Moves progress messages to stderr when the report goes to stdout.
+fn report_on_stdout(args: &ExecuteArgs) {
+    if args.report.is_some() && args.report_file.is_none() {
+        REPORT_ON_STDOUT.store(true, Ordering::Relaxed);
+    }
+}
 
-    // Stop if the file list does not exist.
-    if !Path::new(&file_name_list_path).exists() {
-        println!(
-            "ERROR: Path to file list `{}` does not exist",
-            file_name_list_path
+/// Runs every stage, returning the exit code once files have been handled.
+fn run(select_args: &SelectArgs, args: &ExecuteArgs, jobs: usize) -> Result<ExitCode, Error> {
+    let report = args
+        .report
+        .map(|format| (format, args.report_file.as_deref()));
+    let (file_names, plan, suggestions) = select(select_args, jobs, args.require_empty(), report)?;
+    let missing_list = select_args
+        .missing_list
+        .as_deref()
+        .map(|path| (path, select_args.null));
+    execute(
+        &file_names,
+        plan,
+        args,
+        jobs,
+        false,
+        missing_list,
+        &suggestions,
+    )
+}
+
+/// Writes the plan of a run to a file.
+fn plan(args: PlanArgs) -> Result<ExitCode, Error> {
+    let (file_names, mut plan, suggestions) = select(&args.select, args.jobs, false, None)?;
+    if let Some(algorithm) = args.hash {
+        finder::hash_sources(&mut plan, algorithm)?;
+    }
+    for copy in &plan.copies {
+        say!(
+            "PLAN. {} `{}` to `{}`{}",
+            capitalize(copy.mode.verb()),
+            copy.source.display(),
+            copy.target.display(),
+            matched_by(copy)
         );
-        return;
     }
+    let missing_list = args
+        .select
+        .missing_list
+        .as_deref()
+        .map(|path| (path, args.select.null));
+    report_missing(&file_names, &plan, &suggestions, missing_list)?;
+
+    let plan_file = PlanFile {
+        entries: file_names,
+        plan,
+    };
+    finder::save_plan(&args.output, &plan_file)?;
+    say!(
+        "Wrote plan of {} file(s) to: {}",
+        plan_file.plan.copies.len(),
+        args.output
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Carries out a plan file, returning the exit code once files have been
+/// handled.
+fn apply(args: ApplyArgs) -> Result<ExitCode, Error> {
+    let PlanFile { entries, plan } = finder::load_plan(&args.plan)?;
+    say!(
+        "Read plan of {} file(s) from: {}",
+        plan.copies.len(),
+        args.plan
+    );
+    finder::check_target(&plan.target_dir, args.execute.require_empty())?;
+    execute(&entries, plan, &args.execute, args.jobs, true, None, &[])
+}
 
+/// Reads the file list and the source directory, and works out which files
+/// to copy. The target directory has to exist, and be empty if
+/// `require_empty` is set.
+///
+/// When listed names collide, the collisions are written to `report` before
+/// giving up.
+fn select(
+    args: &SelectArgs,
+    jobs: usize,
+    require_empty: bool,
+    report: Option<(ReportFormat, Option<&str>)>,
+) -> Result<(Vec<String>, Plan, Vec<Suggestion>), Error> {
     // Read the file list.
-    let reader = BufReader::new(File::open(file_name_list_path).expect("ERROR: Cannot open file."));
+    let list_options = ListOptions {
+        nul_separated: args.null,
+        format: args.list_format,
+        hash: args.match_hash.unwrap_or_default(),
+    };
+    let entries = finder::load_lists(&args.file_list, &list_options)?;
+    let file_names: Vec<String> = entries.iter().map(|entry| entry.name.clone()).collect();
+
+    // Turn the file list into patterns.
+    let match_options = MatchOptions {
+        ignore_case: args.ignore_case,
+        normalization: args.normalize,
+        stems: args.stems,
+        extensions: args.extensions.clone(),
+    };
+    let matcher = match args.match_hash {
+        Some(algorithm) => Matcher::with_digests(&entries, algorithm)?,
+        None => Matcher::with_options(&file_names, match_options)?,
+    };
+
+    // Stop if the destination directory does not exist, or is not empty
+    // unless it is allowed to be.
+    let absolute_target = finder::check_target(&args.target_dir, require_empty)?;
+
+    // Read the files in the source directory into an index.
+    let absolute_source =
+        path::absolute(&args.source_dir).map_err(|e| Error::io(&args.source_dir, e))?;
+    say!("Reading files from: {}", absolute_source.display());
+    let hash_cache = match &args.hash_cache {
+        Some(path) => Some(Arc::new(HashCache::open(path)?)),
+        None => None,
+    };
+    let suggest = args.suggest || args.accept_suggestions.is_some();
+    let index_options = IndexOptions {
+        jobs,
+        stop_when_found: args.stop_when_found,
+        hash_cache: hash_cache.clone(),
+        keep_unmatched: suggest,
+    };
+    let index = finder::index_source(&absolute_source, &matcher, &index_options)?;
+    if let Some(hash_cache) = &hash_cache {
+        hash_cache.save()?;
+    }
+    say!("Found {} matching file name(s).", index.len());
+    if index.stopped_early() {
+        say!("Stopped early, every list entry was found.");
+    }
 
-    // Store the file names in a vector.
-    let mut file_names = Vec::new();
-    for line in reader.lines() {
-        let line = line.expect("ERROR: Cannot read line.");
-        file_names.push(line);
+    // Report every file name that was found more than once.
+    let mut collision_count = 0;
+    for collision in index.collisions() {
+        collision_count += 1;
+        say!("COLLISION: `{}` found at:", collision.file_name);
+        for file in collision.files {
+            say!("    `{}`", file.path.display());
+        }
+    }
+    if collision_count > 0 {
+        say!("Found {} file name collision(s).", collision_count);
     }
 
-    // Get the absolute path of the source directory.
-    let source_directory = args.source_dir;
-    let absolute_source = match path::absolute(source_directory) {
-        Ok(p) => p,
-        Err(e) => panic!("ERROR: Problem with file: {:?}", e),
+    // Work out which files to copy.
+    let mut options = PlanOptions {
+        collisions: args.on_collision,
+        preserve_structure: args.preserve_structure,
+        mode: args.mode,
+        entries,
+        accepted: Vec::new(),
+    };
+    let make_plan = |options: &PlanOptions| -> Result<Plan, Error> {
+        finder::plan_matches(&matcher, &index, &absolute_target, options).or_else(|e| {
+            match &e {
+                PlanError::Collisions(names) => {
+                    if let Some((format, report_file)) = report {
+                        let records = finder::collision_records(&matcher, &index, names);
+                        write_report(format, report_file, &records)?;
+                    }
+                }
+                PlanError::SharedTargets(targets) => {
+                    for (target, sources) in targets {
+                        say!("COLLISION: `{}` would be written by:", target.display());
+                        for source in sources {
+                            say!("    `{}`", source.display());
+                        }
+                    }
+                    if let Some((format, report_file)) = report {
+                        let records = finder::shared_target_records(&matcher, &index, targets);
+                        write_report(format, report_file, &records)?;
+                    }
+                }
+            }
+            Err(e.into())
+        })
     };
-    let absolute_source_string: String = absolute_source.display().to_string();
+    let mut plan = make_plan(&options)?;
 
-    // Stop if the source directory does not exist.
-    if !Path::new(&absolute_source).exists() {
-        println!(
-            "ERROR: Source path `{}` does not exist",
-            absolute_source_string
-        );
-        return;
-    }
-
-    // Read the files in the source directory into a hashmap.
-    println!("Reading files from: {}", absolute_source_string);
-    let mut source_files = HashMap::new();
-
-    for entry in WalkDir::new(absolute_source)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| !e.file_type().is_dir())
-    {
-        let file_name = String::from(entry.file_name().to_string_lossy());
-        let full_path = String::from(entry.path().to_string_lossy());
-        // Deduplicate file names.
-        println!("Inserting: `{}`", &file_name);
-        source_files.insert(file_name, full_path);
-    }
-
-    // Get the absolute path of the destination directory.
-    let target_directory = args.target_dir;
-    let absolute_target = match path::absolute(target_directory) {
-        Ok(p) => p,
-        Err(e) => panic!("ERROR: Problem with file: {:?}", e),
+    // Suggest names for the entries that matched nothing, and plan again
+    // with the suggestions that are close enough to be taken as matches.
+    let mut suggestions = Vec::new();
+    if suggest {
+        let suggest_options = SuggestOptions {
+            min_score: args.suggest_threshold,
+            jobs,
+            ..SuggestOptions::default()
+        };
+        suggestions = finder::suggest(&matcher, &plan.unmatched, &index, &suggest_options);
+        if let Some(confidence) = args.accept_suggestions {
+            options.accepted = finder::accept_suggestions(&suggestions, &index, confidence);
+            for (path, i) in &options.accepted {
+                // Suggestions for an entry come together, best first.
+                let first = suggestions.partition_point(|s| s.position < *i);
+                let best = suggestions.get(first);
+                say!(
+                    "ACCEPTED: `{}` for `{}`, {:.0}% similar",
+                    path.display(),
+                    matcher.entries()[*i],
+                    best.map_or(0.0, |s| s.score * 100.0)
+                );
+            }
+            if !options.accepted.is_empty() {
+                plan = make_plan(&options)?;
+            }
+        }
+    }
+    Ok((file_names, plan, suggestions))
+}
+
+/// Puts the files in `plan` in place, or shows what would be done on a dry
+/// run, and reports the outcome. With `check_sources` set, files whose
+/// source changed since the plan was made are refused.
+fn execute(
+    file_names: &[String],
+    mut plan: Plan,
+    args: &ExecuteArgs,
+    jobs: usize,
+    check_sources: bool,
+    missing_list: Option<(&str, bool)>,
+    suggestions: &[Suggestion],
+) -> Result<ExitCode, Error> {
+    // Open the journal, and show the entries whose files were moved away by
+    // an earlier run, which `execute_plan` counts as matched.
+    let disable_dry_run = args.disable_dry_run;
+    let journal = match &args.journal {
+        Some(path) if args.resume => Some(Journal::resume(path, args.hash)?),
+        Some(path) if disable_dry_run => Some(Journal::create(path, args.hash)?),
+        _ => None,
+    };
+    if let Some(journal) = &journal {
+        for entry in journal.apply_to(&mut plan) {
+            say!("Found `{}` completed by an earlier run.", entry);
+        }
+    }
+
+    // Copy the files.
+    let execute_options = ExecuteOptions {
+        dry_run: !disable_dry_run,
+        hash: (args.verify || args.manifest.is_some()).then_some(args.hash),
+        verify: args.verify,
+        retries: args.retries,
+        keep_going: args.keep_going,
+        jobs,
+        existing: args.existing.unwrap_or_default(),
+        preserve: if args.archive {
+            Attribute::ALL.to_vec()
+        } else {
+            args.preserve.clone()
+        },
+        check_sources,
+        journal: journal.map(Arc::new),
     };
-    let absolute_target_string: String = absolute_target.display().to_string();
+    let execution = finder::execute_plan(&mut plan, &execute_options, |copy, outcome| {
+        let matched_by = matched_by(copy);
+        let transferred = outcome.and_then(|o| o.result.as_ref().ok());
+        if let Some(Err(e)) = outcome.map(|o| &o.result) {
+            say!("ERROR: {}", e);
+        } else if let Some(t) = transferred.filter(|t| t.action == Action::Resumed) {
+            say!(
+                "Already done `{}` to `{}`{}, checked against the journal",
+                copy.source.display(),
+                t.target.display(),
+                matched_by
+            );
+        } else if let Some(t) = transferred.filter(|t| t.action == Action::Skipped) {
+            say!(
+                "Kept existing `{}`, not {} `{}`{}",
+                t.target.display(),
+                copy.mode.verb(),
+                copy.source.display(),
+                matched_by
+            );
+        } else if disable_dry_run {
+            let (target, note) = match transferred {
+                Some(t) if t.action == Action::Overwritten => (&t.target, " (overwritten)".into()),
+                Some(t) if t.action == Action::Updated => (&t.target, " (updated)".into()),
+                Some(t) if t.action == Action::Renamed => (
+                    &t.target,
+                    format!(" (renamed, `{}` exists)", copy.target.display()),
+                ),
+                _ => (&copy.target, String::new()),
+            };
+            say!(
+                "{} `{}` to `{}`{}{}",
+                capitalize(copy.mode.verb()),
+                copy.source.display(),
+                target.display(),
+                matched_by,
+                note
+            );
+        } else {
+            say!(
+                "DRY RUN. Not {} `{}` to `{}`{}",
+                copy.mode.verb(),
+                copy.source.display(),
+                copy.target.display(),
+                matched_by
+            );
+        }
+    })?;
 
-    // Stop if the destination directory does not exist.
-    if !Path::new(&absolute_target).exists() {
-        println!(
-            "ERROR: Target path `{}` does not exist",
-            absolute_target_string
-        );
-        return;
+    // Report the temporary files left behind by an interrupted run, which
+    // were removed before copying.
+    for temp in &execution.removed {
+        say!("Removed leftover temporary file `{}`", temp.display());
     }
 
-    // Stop if the destination directory is not empty.
-    if absolute_target.read_dir().unwrap().next().is_some() {
-        println!(
-            "ERROR: Target path `{}` is not empty",
-            absolute_target_string
-        );
-        return;
+    // Report the attributes that could not be carried over. Failed copies
+    // were reported as they happened.
+    let outcomes = execution.outcomes;
+    let transferred: Vec<_> = outcomes
+        .iter()
+        .filter_map(|outcome| outcome.result.as_ref().ok())
+        .collect();
+    for t in &transferred {
+        for unpreserved in &t.unpreserved {
+            say!(
+                "WARNING: Could not preserve {} of `{}`: {}",
+                unpreserved.attribute.name(),
+                t.target.display(),
+                unpreserved.reason
+            );
+        }
+    }
+    let failed = outcomes.len() - transferred.len();
+    if failed > 0 {
+        say!("{} of {} file(s) failed.", failed, plan.copies.len());
     }
 
-    // Copy the files to the destination directory.
-    let disable_dry_run = args.disable_dry_run;
-    for (file_name, full_path) in source_files.into_iter() {
-        if file_names.contains(&file_name) {
-            let target_path = format!("{}/{}", absolute_target_string, file_name);
-            if disable_dry_run {
-                println!("Copying `{}` to `{}`", full_path, target_path);
-                fs::copy(full_path, target_path).expect("Cannot copy file.");
+    // Write the checksums of the files that were put in place.
+    if let Some(manifest) = args.manifest.as_ref().filter(|_| disable_dry_run) {
+        let entries: Vec<_> = transferred
+            .iter()
+            .filter_map(|t| Some((t.hash.clone()?, t.target.as_path())))
+            .collect();
+        finder::write_manifest(manifest, &entries)?;
+        say!("Wrote checksum manifest to: {}", manifest);
+    }
+
+    report_missing(file_names, &plan, suggestions, missing_list)?;
+
+    // Write the machine-readable report.
+    if let Some(format) = args.report {
+        let records = finder::build_records(file_names, &plan, &outcomes, !disable_dry_run);
+        write_report(format, args.report_file.as_deref(), &records)?;
+    }
+
+    Ok(exit_code(failed, transferred.len()))
+}
+
+/// Parses a similarity given on the command line, from 0 to 1.
+fn parse_score(text: &str) -> Result<f64, String> {
+    let score: f64 = text
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", text))?;
+    if !(0.0..=1.0).contains(&score) {
+        return Err(format!("{} is not between 0 and 1", score));
+    }
+    Ok(score)
+}
+
+/// Reports the list entries that matched nothing, with the names suggested
+/// for them, also writing them to `missing_list` when given, separated by
+/// NUL bytes if its flag is set.
+fn report_missing(
+    file_names: &[String],
+    plan: &Plan,
+    suggestions: &[Suggestion],
+    missing_list: Option<(&str, bool)>,
+) -> Result<(), Error> {
+    let mut suggested: HashMap<usize, Vec<&Suggestion>> = HashMap::new();
+    for suggestion in suggestions {
+        suggested
+            .entry(suggestion.position)
+            .or_default()
+            .push(suggestion);
+    }
+    let unmatched: HashSet<&str> = plan.unmatched.iter().map(String::as_str).collect();
+    let missing = file_names
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| unmatched.contains(entry.as_str()));
+    for (position, entry) in missing {
+        say!("MISSING: `{}`", entry);
+        for suggestion in suggested.get(&position).into_iter().flatten() {
+            say!(
+                "    Did you mean `{}`? ({:.0}% similar)",
+                suggestion.file_name,
+                suggestion.score * 100.0
+            );
+        }
+    }
+    say!(
+        "Matched {} of {} list entries, {} not found.",
+        plan.matched.len(),
+        file_names.len(),
+        plan.unmatched.len()
+    );
+    if let Some((missing_list, nul_separated)) = missing_list {
+        let options = ListOptions {
+            nul_separated,
+            ..ListOptions::default()
+        };
+        finder::save_list_with(missing_list, &plan.unmatched, &options)?;
+        say!("Wrote missing list entries to: {}", missing_list);
+    }
+    Ok(())
+}
+
+/// Reverts the files recorded in a journal, returning the exit code once
+/// every file has been handled.
+fn undo(args: UndoArgs) -> Result<ExitCode, Error> {
+    // Read the journal.
+    let history = finder::read_history(&args.journal)?;
+    say!(
+        "Found {} file(s) put in place in: {}",
+        history.completed.len(),
+        args.journal
+    );
+
+    // Revert the files, the last one put in place first. Every file is
+    // checked before it is touched, and left alone if it changed.
+    let mut failed = 0;
+    for completed in history.completed.iter().rev() {
+        let result = finder::check_undo(completed).and_then(|revert| {
+            if args.disable_dry_run {
+                say!(
+                    "{} `{}`",
+                    capitalize(revert.verb()),
+                    completed.target.display()
+                );
+                finder::undo_file(completed, revert, &history)
             } else {
-                println!("DRY RUN. Not copying `{}` to `{}`", full_path, target_path);
+                say!(
+                    "DRY RUN. Not {} `{}`",
+                    revert.verb(),
+                    completed.target.display()
+                );
+                Ok(())
             }
+        });
+        if let Err(e) = result {
+            say!("ERROR: {}", e);
+            failed += 1;
         }
     }
+    if failed > 0 {
+        say!(
+            "{} of {} file(s) could not be reverted.",
+            failed,
+            history.completed.len()
+        );
+    }
+    Ok(exit_code(failed, history.completed.len() - failed))
+}
+
+/// Picks the exit code for a run where `failed` files failed and
+/// `succeeded` files were handled.
+fn exit_code(failed: usize, succeeded: usize) -> ExitCode {
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else if succeeded == 0 {
+        ExitCode::from(EXIT_TOTAL_FAILURE)
+    } else {
+        ExitCode::from(EXIT_PARTIAL_FAILURE)
+    }
+}
+
+/// Writes the report to `path`, or to stdout when no path is given.
+fn write_report(format: ReportFormat, path: Option<&str>, records: &[Record]) -> Result<(), Error> {
+    match path {
+        Some(path) => File::create(path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                finder::write_report(&mut writer, format, records)?;
+                writer.flush()
+            })
+            .map_err(|e| Error::io(path, e))?,
+        None => finder::write_report(io::stdout().lock(), format, records)
+            .map_err(|e| Error::io("<stdout>", e))?,
+    }
+    if let Some(path) = path {
+        say!("Wrote report to: {}", path);
+    }
+    Ok(())
+}
+
+/// Shows which pattern matched a file, unless its name was listed as is.
+fn matched_by(copy: &PlannedCopy) -> String {
+    if copy.entry == copy.file_name {
+        String::new()
+    } else {
+        format!(" (matched `{}`)", copy.entry)
+    }
+}
+
+/// Upper-cases the first letter of `text`.
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_pick_the_exit_code() {
+        assert_eq!(exit_code(0, 0), ExitCode::SUCCESS);
+        assert_eq!(exit_code(0, 3), ExitCode::SUCCESS);
+        assert_eq!(exit_code(1, 2), ExitCode::from(EXIT_PARTIAL_FAILURE));
+        assert_eq!(exit_code(2, 0), ExitCode::from(EXIT_TOTAL_FAILURE));
+    }
 }
@@ -0,0 +1,662 @@
+//! Deciding which file names are wanted by the file list.
+
+use crate::index::SourceFile;
+use crate::list::{parse_checksum, ListEntry};
+use crate::verify::HashAlgorithm;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::RegexSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+
+/// Prefix that marks a list entry as a regular expression.
+pub const REGEX_PREFIX: &str = "re:";
+
+/// Unicode normalization applied to names before they are compared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Normalization {
+    /// Compare names as they are.
+    #[default]
+    None,
+    /// Canonical composition, so `e` followed by a combining accent equals `é`.
+    Nfc,
+    /// Compatibility composition, which also folds ligatures, full-width
+    /// forms and similar variants.
+    Nfkc,
+}
+
+/// Options that change how names are compared.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatchOptions {
+    /// Compare names without regard to case.
+    pub ignore_case: bool,
+    /// Unicode normalization applied to both list entries and file names.
+    pub normalization: Normalization,
+    /// Also match list entries against file names with one or more of their
+    /// extensions removed, so `DSC_0042` finds `DSC_0042.NEF` and
+    /// `DSC_0042.NEF.xmp`. Regular expressions are still matched against
+    /// the whole name.
+    pub stems: bool,
+    /// When matching stems, only match files with one of these extensions,
+    /// compared without regard to case. Every file is matched when empty.
+    pub extensions: Vec<String>,
+}
+
+impl MatchOptions {
+    /// Brings a name into the form used for comparisons.
+    ///
+    /// The original spelling is only needed for display and target names, so
+    /// this is applied to list entries and file names alike.
+    pub fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let name: Cow<str> = match self.normalization {
+            Normalization::None => Cow::Borrowed(name),
+            Normalization::Nfc => Cow::Owned(name.nfc().collect()),
+            Normalization::Nfkc => Cow::Owned(name.nfkc().collect()),
+        };
+        if self.ignore_case {
+            Cow::Owned(name.to_lowercase())
+        } else {
+            name
+        }
+    }
+}
+
+/// Matches file names against the entries of a file list.
+///
+/// An entry is one of:
+///
+/// - a plain file name, matched exactly;
+/// - a glob such as `*.RAW` or `IMG_20??_*`, used for any entry containing
+///   `*`, `?`, `[` or `{` that is a valid glob;
+/// - a regular expression such as `re:^invoice-\d{6}\.pdf$`, used for any
+///   entry starting with [`REGEX_PREFIX`];
+/// - a path such as `march/report.pdf`, used for any other entry containing
+///   `/`, which matches files whose path relative to the source directory
+///   ends with it. Paths starting with `/` or `./` must match the whole
+///   relative path. Paths may contain glob syntax, where `*` does not match
+///   `/`.
+///
+/// Entries such as `scan[1.tif`, which look like globs but are not valid
+/// ones, are matched as plain names or paths. Globs are also matched
+/// exactly, so a file really called `photo[1].jpg` is still found by that
+/// entry. When [`MatchOptions::stems`] is set, plain names, globs and paths
+/// also match files that only add extensions.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    options: MatchOptions,
+    extensions: Vec<String>,
+    entries: Vec<String>,
+    exact: HashMap<String, Vec<usize>>,
+    globs: GlobSet,
+    glob_entries: Vec<usize>,
+    regexes: RegexSet,
+    regex_entries: Vec<usize>,
+    paths: GlobSet,
+    path_names: GlobSet,
+    path_entries: Vec<usize>,
+    path_patterns: bool,
+    digests: HashMap<String, Vec<usize>>,
+    digest_algorithm: Option<HashAlgorithm>,
+    digest_sizes: Option<HashSet<u64>>,
+}
+
+/// A list entry that could not be turned into a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError {
+    /// The list entry, as written.
+    pub entry: String,
+    /// Why it was rejected.
+    pub reason: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid pattern `{}`: {}", self.entry, self.reason)
+    }
+}
+
+impl Error for PatternError {}
+
+impl Matcher {
+    /// Builds a matcher from the lines of a file list that compares names
+    /// exactly.
+    pub fn new<S: AsRef<str>>(entries: &[S]) -> Result<Matcher, PatternError> {
+        Matcher::with_options(entries, MatchOptions::default())
+    }
+
+    /// Builds a matcher from the lines of a file list.
+    pub fn with_options<S: AsRef<str>>(
+        entries: &[S],
+        options: MatchOptions,
+    ) -> Result<Matcher, PatternError> {
+        let entries: Vec<String> = entries.iter().map(|e| e.as_ref().to_string()).collect();
+
+        let mut exact = HashMap::new();
+        let mut globs = GlobSetBuilder::new();
+        let mut glob_entries = Vec::new();
+        let mut regexes = Vec::new();
+        let mut regex_entries = Vec::new();
+        let mut paths = GlobSetBuilder::new();
+        let mut path_names = GlobSetBuilder::new();
+        let mut path_entries = Vec::new();
+        let mut path_patterns = false;
+        for (i, entry) in entries.iter().enumerate() {
+            if let Some(pattern) = entry.strip_prefix(REGEX_PREFIX) {
+                // Lower-casing a regex would change escapes such as `\D`, so
+                // case is ignored by the regex engine instead.
+                let mut pattern = MatchOptions {
+                    ignore_case: false,
+                    ..options.clone()
+                }
+                .normalize(pattern)
+                .into_owned();
+                if options.ignore_case {
+                    pattern = format!("(?i){}", pattern);
+                }
+                regexes.push(pattern);
+                regex_entries.push(i);
+                continue;
+            }
+            let key = options.normalize(entry).into_owned();
+            if key.contains('/') {
+                // Paths are matched as globs, which anchor them at the end,
+                // and at the start unless they are given `**/`. Files are
+                // first picked by the last part alone, so only the paths of
+                // likely files have to be built.
+                let path_glob = |pattern: &str| {
+                    GlobBuilder::new(pattern)
+                        .literal_separator(true)
+                        .build()
+                        .map_err(|e| PatternError {
+                            entry: entry.clone(),
+                            reason: e.kind().to_string(),
+                        })
+                };
+                // Paths that are not valid globs are matched literally.
+                let pattern = is_valid_glob(&key);
+                let key = if pattern || !is_glob(&key) {
+                    key
+                } else {
+                    globset::escape(&key)
+                };
+                let path = match key.strip_prefix("./").or_else(|| key.strip_prefix('/')) {
+                    Some(path) => path.to_string(),
+                    None => format!("**/{}", key),
+                };
+                let name = path.rsplit('/').next().unwrap_or_default();
+                path_names.add(path_glob(name)?);
+                paths.add(path_glob(&path)?);
+                path_entries.push(i);
+                path_patterns |= pattern;
+                continue;
+            }
+            // Names such as `scan[1.tif` look like globs but are not valid
+            // ones, so they are only matched exactly.
+            if let Some(glob) = is_glob(&key).then(|| Glob::new(&key).ok()).flatten() {
+                globs.add(glob);
+                glob_entries.push(i);
+            }
+            exact.entry(key).or_insert_with(Vec::new).push(i);
+        }
+
+        // Compile every regex on its own first, so a bad one can be named.
+        for (pattern, &i) in regexes.iter().zip(&regex_entries) {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(PatternError {
+                    entry: entries[i].clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+
+        let invalid = |reason: String| PatternError {
+            entry: String::new(),
+            reason,
+        };
+        Ok(Matcher {
+            globs: globs.build().map_err(|e| invalid(e.to_string()))?,
+            regexes: RegexSet::new(&regexes).map_err(|e| invalid(e.to_string()))?,
+            paths: paths.build().map_err(|e| invalid(e.to_string()))?,
+            path_names: path_names.build().map_err(|e| invalid(e.to_string()))?,
+            extensions: options
+                .extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect(),
+            options,
+            entries,
+            exact,
+            glob_entries,
+            regex_entries,
+            path_entries,
+            path_patterns,
+            digests: HashMap::new(),
+            digest_algorithm: None,
+            digest_sizes: None,
+        })
+    }
+
+    /// Builds a matcher that finds files by their contents rather than their
+    /// name.
+    ///
+    /// Each entry holds the hex digest of a wanted file made with
+    /// `algorithm`, either in its `hash` field or as its name. A name may be
+    /// a line of a `sha256sum` or `b3sum` manifest, as only the digest at its
+    /// start is used, without the `\` that marks a line whose file name was
+    /// escaped. When every entry gives a size, only files of those sizes need
+    /// to be hashed.
+    pub fn with_digests(
+        entries: &[ListEntry],
+        algorithm: HashAlgorithm,
+    ) -> Result<Matcher, PatternError> {
+        let mut digests: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut sizes = HashSet::new();
+        let mut all_sized = true;
+        for (i, entry) in entries.iter().enumerate() {
+            let invalid = |reason: String| PatternError {
+                entry: entry.name.clone(),
+                reason,
+            };
+            let checksum = match &entry.checksum {
+                Some(checksum) => Some(checksum.clone()),
+                None => entry
+                    .name
+                    .split_whitespace()
+                    .next()
+                    .map(|digest| digest.strip_prefix('\\').unwrap_or(digest))
+                    .and_then(|digest| parse_checksum(digest, algorithm)),
+            };
+            let Some(checksum) = checksum else {
+                return Err(invalid("not a hex digest".into()));
+            };
+            if checksum.algorithm != algorithm {
+                return Err(invalid(format!("not a {} digest", algorithm.name())));
+            }
+            digests.entry(checksum.digest).or_default().push(i);
+            match entry.size {
+                Some(size) => {
+                    sizes.insert(size);
+                }
+                None => all_sized = false,
+            }
+        }
+
+        // Start from a matcher without names, so only the digests match.
+        let mut matcher = Matcher::with_options(&[] as &[&str], MatchOptions::default())?;
+        matcher.entries = entries.iter().map(|entry| entry.name.clone()).collect();
+        matcher.digests = digests;
+        matcher.digest_algorithm = Some(algorithm);
+        matcher.digest_sizes = all_sized.then_some(sizes);
+        Ok(matcher)
+    }
+
+    /// How names are compared.
+    pub fn options(&self) -> &MatchOptions {
+        &self.options
+    }
+
+    /// Whether any list entry is a glob or a regular expression, or may
+    /// match several files as a stem.
+    pub fn has_patterns(&self) -> bool {
+        !self.glob_entries.is_empty()
+            || !self.regex_entries.is_empty()
+            || self.path_patterns
+            || self.options.stems
+    }
+
+    /// Every list entry, in list order.
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// The hash function files have to be hashed with to be matched, when
+    /// the matcher finds files by their contents.
+    pub fn digest_algorithm(&self) -> Option<HashAlgorithm> {
+        self.digest_algorithm
+    }
+
+    /// Whether a file of `size` bytes may be matched by its contents, so
+    /// has to be hashed.
+    pub fn wants_size(&self, size: u64) -> bool {
+        self.digest_algorithm.is_some()
+            && self
+                .digest_sizes
+                .as_ref()
+                .is_none_or(|sizes| sizes.contains(&size))
+    }
+
+    /// Returns the position in the list of every entry that holds `digest`,
+    /// in list order.
+    pub fn matching_digest(&self, digest: &str) -> Vec<usize> {
+        self.digests.get(digest).cloned().unwrap_or_default()
+    }
+
+    /// Returns the position in the list of every entry that matches `file`,
+    /// by its contents when it was hashed during indexing, or else by its
+    /// path.
+    pub fn matching_entries_for(&self, file: &SourceFile) -> Vec<usize> {
+        match &file.checksum {
+            Some(checksum) if Some(checksum.algorithm) == self.digest_algorithm => {
+                self.matching_digest(&checksum.digest)
+            }
+            _ => self.matching_entries_at(&file.relative_path),
+        }
+    }
+
+    /// Returns the first list entry that matches `file_name`.
+    ///
+    /// Exact names win over globs, and globs over regular expressions. The
+    /// entry is returned as it was written in the list. Path entries are
+    /// left out, as they need the path of the file.
+    pub fn find(&self, file_name: &str) -> Option<&str> {
+        self.find_index(file_name).map(|i| self.entries[i].as_str())
+    }
+
+    /// Whether any list entry other than a path matches `file_name`.
+    pub fn is_match(&self, file_name: &str) -> bool {
+        self.find_index(file_name).is_some()
+    }
+
+    /// Whether any list entry matches the file at `relative_path`, relative
+    /// to the source directory.
+    pub fn is_match_at(&self, relative_path: &Path) -> bool {
+        let file_name = relative_path.file_name().unwrap_or_default();
+        let file_name = file_name.to_string_lossy();
+        if self.is_match(&file_name) {
+            return true;
+        }
+        let file_name = self.options.normalize(&file_name);
+        self.names(&file_name)
+            .any(|name| self.path_names.is_match(name))
+            && !self.matching_paths(relative_path).is_empty()
+    }
+
+    /// Returns the position in the list of every entry other than a path
+    /// that matches `file_name`, in list order.
+    pub fn matching_entries(&self, file_name: &str) -> Vec<usize> {
+        let file_name = self.options.normalize(file_name);
+        let file_name = file_name.as_ref();
+        if !self.has_allowed_extension(file_name) {
+            return Vec::new();
+        }
+        let mut found = Vec::new();
+        for name in self.names(file_name) {
+            found.extend(self.exact.get(name).into_iter().flatten());
+            found.extend(
+                self.globs
+                    .matches(name)
+                    .into_iter()
+                    .map(|i| self.glob_entries[i]),
+            );
+        }
+        found.extend(
+            self.regexes
+                .matches(file_name)
+                .iter()
+                .map(|i| self.regex_entries[i]),
+        );
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    /// Returns the position in the list of every entry that matches the file
+    /// at `relative_path`, relative to the source directory, in list order.
+    pub fn matching_entries_at(&self, relative_path: &Path) -> Vec<usize> {
+        let file_name = relative_path.file_name().unwrap_or_default();
+        let mut found = self.matching_entries(&file_name.to_string_lossy());
+        if self.path_entries.is_empty() {
+            return found;
+        }
+        found.extend(self.matching_paths(relative_path));
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    /// Returns the position in the list of every path entry that matches the
+    /// file at `relative_path`.
+    fn matching_paths(&self, relative_path: &Path) -> Vec<usize> {
+        let path = self.path_key(relative_path);
+        let (dir, file_name) = match path.rsplit_once('/') {
+            Some((dir, file_name)) => (format!("{}/", dir), file_name),
+            None => (String::new(), path.as_str()),
+        };
+        if !self.has_allowed_extension(file_name) {
+            return Vec::new();
+        }
+        let mut found = Vec::new();
+        for name in self.names(file_name) {
+            let path = format!("{}{}", dir, name);
+            found.extend(
+                self.paths
+                    .matches(path.as_str())
+                    .into_iter()
+                    .map(|i| self.path_entries[i]),
+            );
+        }
+        found
+    }
+
+    /// Every name a file called `file_name` can be listed under: the name
+    /// itself and, when matching stems, the name with one or more of its
+    /// extensions removed.
+    fn names<'a>(&self, file_name: &'a str) -> impl Iterator<Item = &'a str> {
+        let stems = file_name
+            .match_indices('.')
+            .map(|(i, _)| &file_name[..i])
+            .filter(|stem| !stem.is_empty());
+        let stems = self.options.stems.then_some(stems).into_iter().flatten();
+        std::iter::once(file_name).chain(stems)
+    }
+
+    /// Whether `file_name` has one of the allowed extensions, when stems
+    /// are matched and the extensions are limited.
+    fn has_allowed_extension(&self, file_name: &str) -> bool {
+        if !self.options.stems || self.extensions.is_empty() {
+            return true;
+        }
+        match file_name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                let extension = extension.to_lowercase();
+                self.extensions.contains(&extension)
+            }
+            _ => false,
+        }
+    }
+
+    /// Brings a relative path into the form path entries are matched
+    /// against, with `/` between its parts.
+    fn path_key(&self, relative_path: &Path) -> String {
+        let parts: Vec<_> = relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        self.options.normalize(&parts.join("/")).into_owned()
+    }
+
+    fn find_index(&self, file_name: &str) -> Option<usize> {
+        let file_name = self.options.normalize(file_name);
+        let file_name = file_name.as_ref();
+        if !self.has_allowed_extension(file_name) {
+            return None;
+        }
+        let exact = self
+            .names(file_name)
+            .filter_map(|name| self.exact.get(name)?.first())
+            .min();
+        if let Some(&i) = exact {
+            return Some(i);
+        }
+        let glob = self
+            .names(file_name)
+            .filter_map(|name| self.globs.matches(name).into_iter().min())
+            .min();
+        if let Some(i) = glob {
+            return Some(self.glob_entries[i]);
+        }
+        self.regexes
+            .matches(file_name)
+            .iter()
+            .next()
+            .map(|i| self.regex_entries[i])
+    }
+}
+
+/// Whether a list entry contains glob syntax.
+pub(crate) fn is_glob(entry: &str) -> bool {
+    entry.contains(['*', '?', '[', '{'])
+}
+
+/// Whether a list entry contains glob syntax and is a valid glob, so is
+/// matched as one.
+pub(crate) fn is_valid_glob(entry: &str) -> bool {
+    is_glob(entry) && Glob::new(entry).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_globs_match_exactly() {
+        let matcher = Matcher::new(&["notes{draft.txt", "scan[1.tif", "a/b[c.txt"]).unwrap();
+        assert_eq!(matcher.matching_entries("notes{draft.txt"), [0]);
+        assert_eq!(matcher.matching_entries("scan[1.tif"), [1]);
+        assert!(matcher.matching_entries("scan1.tif").is_empty());
+        assert_eq!(matcher.matching_entries_at(Path::new("x/a/b[c.txt")), [2]);
+        assert!(!matcher.has_patterns());
+    }
+
+    #[test]
+    fn globs_also_match_exactly() {
+        let matcher = Matcher::new(&["photo[1].jpg"]).unwrap();
+        assert_eq!(matcher.matching_entries("photo1.jpg"), [0]);
+        assert_eq!(matcher.matching_entries("photo[1].jpg"), [0]);
+    }
+
+    #[test]
+    fn paths_match_the_end_of_the_relative_path() {
+        let matcher = Matcher::new(&["march/report.pdf", "./top.pdf", "/scans/*.tif"]).unwrap();
+        let at = |path: &str| matcher.matching_entries_at(Path::new(path));
+        assert_eq!(at("2023/march/report.pdf"), [0]);
+        assert_eq!(at("march/report.pdf"), [0]);
+        assert!(at("2023/april/report.pdf").is_empty());
+        assert!(at("2023/xmarch/report.pdf").is_empty());
+        assert!(matcher.matching_entries("report.pdf").is_empty());
+
+        assert_eq!(at("top.pdf"), [1]);
+        assert!(at("a/top.pdf").is_empty());
+        assert_eq!(at("scans/1.tif"), [2]);
+        assert!(at("scans/a/1.tif").is_empty());
+        assert!(at("old/scans/1.tif").is_empty());
+    }
+
+    #[test]
+    fn stems_match_files_adding_extensions() {
+        let options = MatchOptions {
+            stems: true,
+            extensions: vec!["NEF".to_string(), ".xmp".to_string()],
+            ..MatchOptions::default()
+        };
+        let matcher = Matcher::with_options(&["DSC_0042"], options).unwrap();
+        assert_eq!(matcher.matching_entries("DSC_0042.NEF"), [0]);
+        assert_eq!(matcher.matching_entries("DSC_0042.nef"), [0]);
+        assert_eq!(matcher.matching_entries("DSC_0042.NEF.xmp"), [0]);
+        assert!(matcher.matching_entries("DSC_0042.JPG").is_empty());
+        assert!(matcher.matching_entries("DSC_00420.NEF").is_empty());
+        assert!(matcher.has_patterns());
+
+        let matcher = Matcher::with_options(
+            &["DSC_0042"],
+            MatchOptions {
+                stems: true,
+                ..MatchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(matcher.matching_entries("DSC_0042.JPG"), [0]);
+        assert_eq!(matcher.matching_entries("DSC_0042"), [0]);
+    }
+
+    #[test]
+    fn normalization_and_case_folding_apply_to_both_sides() {
+        let options = MatchOptions {
+            ignore_case: true,
+            normalization: Normalization::Nfc,
+            ..MatchOptions::default()
+        };
+        let matcher =
+            Matcher::with_options(&["Cafe\u{301}.JPG", "*.RAW", "re:^IMG\\d+$"], options).unwrap();
+        assert_eq!(matcher.matching_entries("caf\u{e9}.jpg"), [0]);
+        assert_eq!(matcher.find("CAF\u{c9}.jpg"), Some("Cafe\u{301}.JPG"));
+        assert_eq!(matcher.matching_entries("photo.raw"), [1]);
+        assert_eq!(matcher.matching_entries("img42"), [2]);
+
+        let matcher = Matcher::new(&["Cafe\u{301}.JPG"]).unwrap();
+        assert!(matcher.matching_entries("Caf\u{e9}.JPG").is_empty());
+        assert!(matcher.matching_entries("cafe\u{301}.jpg").is_empty());
+    }
+
+    #[test]
+    fn digests_are_read_from_manifest_lines_and_hash_fields() {
+        let a = "a".repeat(64);
+        let b = "B".repeat(64);
+        let c = "c".repeat(64);
+        let entries = vec![
+            ListEntry::from(format!("{}  photos/a.jpg", a)),
+            ListEntry::from(format!("\\{}  photos/back\\\\slash.jpg", b)),
+            ListEntry {
+                name: "c.jpg".to_string(),
+                checksum: parse_checksum(&c, HashAlgorithm::Sha256),
+                ..ListEntry::default()
+            },
+            ListEntry::from(a.clone()),
+        ];
+        let matcher = Matcher::with_digests(&entries, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(matcher.digest_algorithm(), Some(HashAlgorithm::Sha256));
+        assert_eq!(matcher.matching_digest(&a), [0, 3]);
+        assert_eq!(matcher.matching_digest(&b.to_ascii_lowercase()), [1]);
+        assert_eq!(matcher.matching_digest(&c), [2]);
+        assert!(matcher.matching_entries("c.jpg").is_empty());
+
+        let entries = [ListEntry::from("photos/a.jpg".to_string())];
+        let error = Matcher::with_digests(&entries, HashAlgorithm::Sha256).unwrap_err();
+        assert_eq!(error.reason, "not a hex digest");
+        let entries = [ListEntry::from(format!("blake3:{}", a))];
+        let error = Matcher::with_digests(&entries, HashAlgorithm::Sha256).unwrap_err();
+        assert_eq!(error.reason, "not a SHA-256 digest");
+    }
+
+    #[test]
+    fn only_listed_sizes_are_wanted_when_every_entry_has_one() {
+        let sized = |size| ListEntry {
+            name: "a".repeat(64),
+            size,
+            ..ListEntry::default()
+        };
+        let matcher =
+            Matcher::with_digests(&[sized(Some(5)), sized(Some(7))], HashAlgorithm::Sha256)
+                .unwrap();
+        assert!(matcher.wants_size(5));
+        assert!(matcher.wants_size(7));
+        assert!(!matcher.wants_size(6));
+
+        let matcher =
+            Matcher::with_digests(&[sized(Some(5)), sized(None)], HashAlgorithm::Sha256).unwrap();
+        assert!(matcher.wants_size(6));
+
+        let matcher = Matcher::new(&["a.txt"]).unwrap();
+        assert!(!matcher.wants_size(5));
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let error = Matcher::new(&["re:scan[1"]).unwrap_err();
+        assert_eq!(error.entry, "re:scan[1");
+    }
+}
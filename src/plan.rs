@@ -0,0 +1,659 @@
+//! Matching the file list against the source index.
+
+use crate::error;
+use crate::index::{SourceFile, SourceIndex};
+use crate::list::ListEntry;
+use crate::matcher::Matcher;
+use crate::transfer::TransferMode;
+use crate::verify::Checksum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What to do when several source files share a listed file name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum CollisionPolicy {
+    /// Refuse to plan anything.
+    #[default]
+    Error,
+    /// Copy the first file found.
+    KeepFirst,
+    /// Copy the most recently modified file.
+    KeepNewest,
+    /// Copy the largest file.
+    KeepLargest,
+    /// Copy every file, adding `-1`, `-2`, ... to the names of the extra copies,
+    /// skipping numbers taken by other copies.
+    CopyAll,
+    /// Copy every file to its path relative to the source directory.
+    Mirror,
+}
+
+/// Options that change how files are matched and where they are copied to.
+#[derive(Debug, Clone, Default)]
+pub struct PlanOptions {
+    /// What to do when several source files share a listed file name.
+    pub collisions: CollisionPolicy,
+    /// Copy every file to its path relative to the source directory, instead
+    /// of straight into the target directory.
+    ///
+    /// Files that share a name then never clash, so `collisions` is ignored.
+    pub preserve_structure: bool,
+    /// How each file is put in place.
+    pub mode: TransferMode,
+    /// The entries of a structured list, in the same order as those of the
+    /// matcher, giving target paths and expected sizes and hashes. May be
+    /// left empty.
+    pub entries: Vec<ListEntry>,
+    /// Files taken as matches for entries that matched nothing, such as
+    /// accepted suggestions, by full path, with the position of the entry.
+    /// The files have to be in the index, matched or not.
+    pub accepted: Vec<(PathBuf, usize)>,
+}
+
+/// A single file that will be copied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedCopy {
+    /// Name of the file in the source directory.
+    pub file_name: String,
+    /// The list entry that matched the file.
+    pub entry: String,
+    /// Full path of the file in the source directory.
+    pub source: PathBuf,
+    /// Full path the file will be copied to.
+    pub target: PathBuf,
+    /// How the file will be put in place.
+    pub mode: TransferMode,
+    /// Size of the source file in bytes, when it was indexed.
+    pub size: u64,
+    /// Last modification time of the source file, when it was indexed.
+    pub modified: Option<SystemTime>,
+    /// Hash of the source file, when one was taken for the plan.
+    #[serde(default)]
+    pub checksum: Option<Checksum>,
+    /// Size the file list expects the source file to have.
+    #[serde(default)]
+    pub expected_size: Option<u64>,
+    /// Hash the file list expects the source file to have.
+    #[serde(default)]
+    pub expected_checksum: Option<Checksum>,
+}
+
+/// Why a matched file is left out of a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// Another file with the same name was picked by the collision policy.
+    Collision,
+}
+
+/// A matched file that will not be copied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    /// Name of the file in the source directory.
+    pub file_name: String,
+    /// The list entry that matched the file.
+    pub entry: String,
+    /// Full path of the file in the source directory.
+    pub source: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Why the file will not be copied.
+    pub reason: SkipReason,
+}
+
+/// Every copy needed to move the listed files into the target directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    /// Directory the files will be copied into.
+    pub target_dir: PathBuf,
+    /// The copies, sorted by file name.
+    pub copies: Vec<PlannedCopy>,
+    /// Matched files that will not be copied, sorted by file name.
+    pub skipped: Vec<SkippedFile>,
+    /// List entries that matched at least one file, in list order, followed
+    /// by those counted as matched by [`Journal::apply_to`].
+    ///
+    /// [`Journal::apply_to`]: crate::journal::Journal::apply_to
+    pub matched: Vec<String>,
+    /// List entries that did not match any file, in list order.
+    pub unmatched: Vec<String>,
+}
+
+/// Reasons a plan could not be made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanError {
+    /// Listed file names that are shared by several source files, when the
+    /// collision policy is [`CollisionPolicy::Error`].
+    Collisions(Vec<String>),
+    /// Target paths that more than one planned copy would be written to,
+    /// each with the source files that would be written there.
+    SharedTargets(Vec<(PathBuf, Vec<PathBuf>)>),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Collisions(names) => write!(
+                f,
+                "{} listed file name(s) found more than once in the source: {}",
+                names.len(),
+                names.join(", ")
+            ),
+            PlanError::SharedTargets(targets) => {
+                let targets: Vec<_> = targets
+                    .iter()
+                    .map(|(target, _)| target.display().to_string())
+                    .collect();
+                write!(
+                    f,
+                    "{} target path(s) would be written by more than one file: {}",
+                    targets.len(),
+                    targets.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl Error for PlanError {}
+
+/// Checks that `target_dir` exists, and is empty if `require_empty` is set,
+/// returning its absolute path.
+pub fn check_target<P: AsRef<Path>>(
+    target_dir: P,
+    require_empty: bool,
+) -> Result<PathBuf, error::Error> {
+    let target_dir = target_dir.as_ref();
+    let absolute = std::path::absolute(target_dir).map_err(|e| error::Error::io(target_dir, e))?;
+    if !absolute.exists() {
+        return Err(error::Error::TargetNotFound(absolute));
+    }
+    if !require_empty {
+        return Ok(absolute);
+    }
+    let mut entries = absolute
+        .read_dir()
+        .map_err(|e| error::Error::io(&absolute, e))?;
+    if entries.next().is_some() {
+        return Err(error::Error::TargetNotEmpty(absolute));
+    }
+    Ok(absolute)
+}
+
+/// Plans a copy into `target_dir` for every indexed file that is matched by
+/// `matcher`.
+pub fn plan_matches(
+    matcher: &Matcher,
+    index: &SourceIndex,
+    target_dir: &Path,
+    options: &PlanOptions,
+) -> Result<Plan, PlanError> {
+    let mut copies = Vec::new();
+    let mut skipped = Vec::new();
+    let mut collisions = Vec::new();
+    let mut matched = vec![false; matcher.entries().len()];
+    // Copies given a numbered name, which is picked once every other target
+    // is known.
+    let mut numbered = Vec::new();
+
+    // Accepted files are planned along with the files that share their name.
+    let mut accepted: HashMap<&Path, Vec<usize>> = HashMap::new();
+    for (path, i) in &options.accepted {
+        accepted.entry(path.as_path()).or_default().push(*i);
+    }
+    let mut names: BTreeSet<&str> = index.iter().map(|(file_name, _)| file_name).collect();
+    if !accepted.is_empty() {
+        names.extend(
+            index
+                .unmatched()
+                .filter(|(_, files)| {
+                    files
+                        .iter()
+                        .any(|f| accepted.contains_key(f.path.as_path()))
+                })
+                .map(|(file_name, _)| file_name),
+        );
+    }
+
+    for file_name in names {
+        // Path entries match a single file, so each file is matched on its
+        // own. Files whose entry gives them a target of their own cannot
+        // clash with the others, so they are grouped by that entry.
+        let files = index
+            .get(file_name)
+            .iter()
+            .chain(index.get_unmatched(file_name));
+        let mut groups: Vec<Group> = Vec::new();
+        for file in files {
+            let mut entries = matcher.matching_entries_for(file);
+            if let Some(positions) = accepted.get(file.path.as_path()) {
+                entries.extend(positions);
+                entries.sort_unstable();
+                entries.dedup();
+            }
+            let Some(&first) = entries.first() else {
+                continue;
+            };
+            for &i in &entries {
+                matched[i] = true;
+            }
+            let own_target = options
+                .entries
+                .get(first)
+                .is_some_and(|e| e.target.is_some());
+            let key = own_target.then_some(first);
+            match groups.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, group)) => group.push((file, first)),
+                None => groups.push((key, vec![(file, first)])),
+            }
+        }
+
+        for (key, group) in groups {
+            let (folder, rename) = match key.and_then(|i| options.entries[i].target.as_deref()) {
+                Some(target) => split_target(target),
+                None => (PathBuf::new(), None),
+            };
+            let group_files: Vec<&SourceFile> = group.iter().map(|&(file, _)| file).collect();
+            let targets = if options.preserve_structure {
+                group_files
+                    .iter()
+                    .map(|&file| (file, renamed(&file.relative_path, rename.as_deref())))
+                    .collect()
+            } else if group_files.len() > 1 && options.collisions == CollisionPolicy::Error {
+                if !collisions.iter().any(|name| name == file_name) {
+                    collisions.push(file_name.to_string());
+                }
+                continue;
+            } else {
+                resolve_collision(
+                    file_name,
+                    rename.as_deref(),
+                    &group_files,
+                    options.collisions,
+                )
+            };
+            let copy_all = !options.preserve_structure
+                && options.collisions == CollisionPolicy::CopyAll
+                && group_files.len() > 1;
+            for (n, (file, first)) in group.into_iter().enumerate() {
+                let entry = &matcher.entries()[first];
+                let Some((_, target)) = targets.iter().find(|(kept, _)| kept.path == file.path)
+                else {
+                    skipped.push(SkippedFile {
+                        file_name: file_name.to_string(),
+                        entry: entry.to_string(),
+                        source: file.path.clone(),
+                        size: file.size,
+                        reason: SkipReason::Collision,
+                    });
+                    continue;
+                };
+                let list_entry = options.entries.get(first);
+                if copy_all && n > 0 {
+                    numbered.push(copies.len());
+                }
+                copies.push(PlannedCopy {
+                    file_name: file_name.to_string(),
+                    entry: entry.to_string(),
+                    source: file.path.clone(),
+                    target: target_dir.join(&folder).join(target),
+                    mode: options.mode,
+                    size: file.size,
+                    modified: file.modified,
+                    checksum: file.checksum.clone(),
+                    expected_size: list_entry.and_then(|e| e.size),
+                    expected_checksum: list_entry.and_then(|e| e.checksum.clone()),
+                });
+            }
+        }
+    }
+
+    if !collisions.is_empty() {
+        return Err(PlanError::Collisions(collisions));
+    }
+    number_copies(&mut copies, &numbered);
+    let shared = shared_targets(&copies);
+    if !shared.is_empty() {
+        return Err(PlanError::SharedTargets(shared));
+    }
+
+    let (matched, unmatched): (Vec<_>, Vec<_>) = matcher
+        .entries()
+        .iter()
+        .zip(matched)
+        .partition(|(_, matched)| *matched);
+
+    Ok(Plan {
+        target_dir: target_dir.to_path_buf(),
+        copies,
+        skipped,
+        matched: matched
+            .into_iter()
+            .map(|(entry, _)| entry.clone())
+            .collect(),
+        unmatched: unmatched
+            .into_iter()
+            .map(|(entry, _)| entry.clone())
+            .collect(),
+    })
+}
+
+/// Matched files that share a name and a target, with the position of the
+/// entry that gave them their own target, if any. Each file comes with the
+/// position of the first entry that matched it.
+type Group<'a> = (Option<usize>, Vec<(&'a SourceFile, usize)>);
+
+/// Splits the target of a list entry into the folder matched files go in,
+/// and the name they are given, if any. Targets ending with `/` only name
+/// a folder.
+fn split_target(target: &str) -> (PathBuf, Option<String>) {
+    if target.ends_with(std::path::is_separator) {
+        return (PathBuf::from(target), None);
+    }
+    let target = Path::new(target);
+    let folder = target.parent().unwrap_or(Path::new("")).to_path_buf();
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    (folder, name)
+}
+
+/// Gives the file at `path` the name `rename`, when set.
+fn renamed(path: &Path, rename: Option<&str>) -> PathBuf {
+    match rename {
+        Some(name) => path.with_file_name(name),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Picks which of the files sharing `file_name` to copy, and the path
+/// relative to the target directory to copy each of them to. Copies are
+/// named `rename` instead of `file_name` when it is set.
+fn resolve_collision<'a>(
+    file_name: &str,
+    rename: Option<&str>,
+    files: &[&'a SourceFile],
+    policy: CollisionPolicy,
+) -> Vec<(&'a SourceFile, PathBuf)> {
+    let file_name = rename.unwrap_or(file_name);
+    let keep = |file: Option<&'a SourceFile>| {
+        file.map(|file| (file, PathBuf::from(file_name)))
+            .into_iter()
+            .collect()
+    };
+
+    if files.len() < 2 {
+        return keep(files.first().copied());
+    }
+
+    match policy {
+        CollisionPolicy::Error | CollisionPolicy::KeepFirst => keep(files.first().copied()),
+        // `max_by_key` returns the last of equal elements, so search from the
+        // back to prefer the first file found on a tie.
+        CollisionPolicy::KeepNewest => keep(files.iter().rev().max_by_key(|f| f.modified).copied()),
+        CollisionPolicy::KeepLargest => keep(files.iter().rev().max_by_key(|f| f.size).copied()),
+        // The numbers are added by `number_copies`, once every target is
+        // known.
+        CollisionPolicy::CopyAll => files
+            .iter()
+            .map(|&file| (file, PathBuf::from(file_name)))
+            .collect(),
+        CollisionPolicy::Mirror => files
+            .iter()
+            .map(|&file| (file, renamed(&file.relative_path, rename)))
+            .collect(),
+    }
+}
+
+/// Gives each copy at a position in `numbered` the lowest numbered name
+/// that no other copy is planned to take, in order.
+fn number_copies(copies: &mut [PlannedCopy], numbered: &[usize]) {
+    let mut taken: HashSet<PathBuf> = copies.iter().map(|copy| copy.target.clone()).collect();
+    for &i in numbered {
+        let target = &copies[i].target;
+        let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+        let mut n = 1;
+        let mut candidate = target.with_file_name(numbered_name(&file_name, n));
+        while taken.contains(&candidate) {
+            n += 1;
+            candidate = target.with_file_name(numbered_name(&file_name, n));
+        }
+        taken.insert(candidate.clone());
+        copies[i].target = candidate;
+    }
+}
+
+/// Finds the targets that more than one copy would be written to, with the
+/// sources of those copies, sorted by target.
+fn shared_targets(copies: &[PlannedCopy]) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut sources: BTreeMap<&Path, Vec<PathBuf>> = BTreeMap::new();
+    for copy in copies {
+        sources
+            .entry(&copy.target)
+            .or_default()
+            .push(copy.source.clone());
+    }
+    sources
+        .into_iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(target, sources)| (target.to_path_buf(), sources))
+        .collect()
+}
+
+/// Adds `-n` before the extension of `file_name`, leaving the first copy
+/// (`n == 0`) unchanged.
+pub(crate) fn numbered_name(file_name: &str, n: usize) -> String {
+    if n == 0 {
+        return file_name.to_string();
+    }
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{}-{}.{}", stem, n, extension),
+        _ => format!("{}-{}", file_name, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{index_source, IndexOptions};
+    use crate::list::{read_entries, ListFormat, ListOptions};
+    use crate::test_util::{planned_copy, TempDir};
+
+    /// Creates `files` below a new directory for the test called `name`, and
+    /// indexes them with `matcher`.
+    fn index(name: &str, files: &[&str], matcher: &Matcher) -> (TempDir, SourceIndex) {
+        let root = TempDir::new(&format!("plan-{}", name));
+        for file in files {
+            root.write(file, file);
+        }
+        let options = IndexOptions {
+            jobs: 1,
+            ..IndexOptions::default()
+        };
+        let index = index_source(&root, matcher, &options).unwrap();
+        (root, index)
+    }
+
+    #[test]
+    fn number_copies_skips_taken_names() {
+        let mut copies = vec![
+            planned_copy("/src/a/x.txt", "/tgt/x.txt"),
+            planned_copy("/src/b/x.txt", "/tgt/x.txt"),
+            planned_copy("/src/c/x.txt", "/tgt/x.txt"),
+            planned_copy("/src/x-1.txt", "/tgt/x-1.txt"),
+        ];
+        number_copies(&mut copies, &[1, 2]);
+        let targets: Vec<_> = copies.iter().map(|copy| copy.target.as_path()).collect();
+        assert_eq!(
+            targets,
+            [
+                Path::new("/tgt/x.txt"),
+                Path::new("/tgt/x-2.txt"),
+                Path::new("/tgt/x-3.txt"),
+                Path::new("/tgt/x-1.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_targets_lists_every_source() {
+        let copies = vec![
+            planned_copy("/src/a.txt", "/tgt/x.txt"),
+            planned_copy("/src/b.txt", "/tgt/y.txt"),
+            planned_copy("/src/c.txt", "/tgt/x.txt"),
+        ];
+        assert_eq!(
+            shared_targets(&copies),
+            [(
+                PathBuf::from("/tgt/x.txt"),
+                vec![PathBuf::from("/src/a.txt"), PathBuf::from("/src/c.txt")]
+            )]
+        );
+    }
+
+    #[test]
+    fn copy_all_numbers_around_listed_names() {
+        let matcher = Matcher::new(&["x.txt", "x-1.txt"]).unwrap();
+        let (root, index) = index("copy-all", &["a/x.txt", "b/x.txt", "x-1.txt"], &matcher);
+        let options = PlanOptions {
+            collisions: CollisionPolicy::CopyAll,
+            ..PlanOptions::default()
+        };
+        let plan = plan_matches(&matcher, &index, Path::new("/tgt"), &options).unwrap();
+        let targets: Vec<_> = plan
+            .copies
+            .iter()
+            .map(|copy| {
+                (
+                    copy.source.strip_prefix(&root).unwrap(),
+                    copy.target.as_path(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (Path::new("x-1.txt"), Path::new("/tgt/x-1.txt")),
+                (Path::new("a/x.txt"), Path::new("/tgt/x.txt")),
+                (Path::new("b/x.txt"), Path::new("/tgt/x-2.txt")),
+            ]
+        );
+    }
+
+    /// Reads the entries of a CSV list, and builds a matcher from them.
+    fn csv_entries(text: &str) -> (Vec<ListEntry>, Matcher) {
+        let entries =
+            read_entries(text.as_bytes(), ListFormat::Csv, &ListOptions::default()).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        let matcher = Matcher::new(&names).unwrap();
+        (entries, matcher)
+    }
+
+    #[test]
+    fn entries_with_the_same_target_are_refused() {
+        let (entries, matcher) = csv_entries("name,target\na.txt,same.txt\nb.txt,same.txt\n");
+        let (root, index) = index("same-target", &["a.txt", "b.txt"], &matcher);
+        let options = PlanOptions {
+            entries,
+            ..PlanOptions::default()
+        };
+        let error = plan_matches(&matcher, &index, Path::new("/tgt"), &options);
+        assert_eq!(
+            error,
+            Err(PlanError::SharedTargets(vec![(
+                PathBuf::from("/tgt/same.txt"),
+                vec![root.join("a.txt"), root.join("b.txt")]
+            )]))
+        );
+    }
+
+    #[test]
+    fn entries_with_their_own_target_do_not_collide() {
+        let (entries, matcher) = csv_entries("name,target\na/x.txt,first/\nb/x.txt,\n");
+        let (_root, index) = index("own-target", &["a/x.txt", "b/x.txt"], &matcher);
+        let options = PlanOptions {
+            entries,
+            ..PlanOptions::default()
+        };
+        let plan = plan_matches(&matcher, &index, Path::new("/tgt"), &options).unwrap();
+        let targets: Vec<_> = plan
+            .copies
+            .iter()
+            .map(|copy| (copy.entry.as_str(), copy.target.as_path()))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                ("a/x.txt", Path::new("/tgt/first/x.txt")),
+                ("b/x.txt", Path::new("/tgt/x.txt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn collisions_are_refused_by_default() {
+        let matcher = Matcher::new(&["x.txt"]).unwrap();
+        let (_root, index) = index("collisions", &["a/x.txt", "b/x.txt"], &matcher);
+        let error = plan_matches(&matcher, &index, Path::new("/tgt"), &PlanOptions::default());
+        assert_eq!(error, Err(PlanError::Collisions(vec!["x.txt".to_string()])));
+    }
+
+    #[test]
+    fn preserved_structure_keeps_relative_paths_below_entry_targets() {
+        let (entries, matcher) =
+            csv_entries("name,target\na/x.txt,first/\nc/y.txt,out/z.txt\nb/x.txt,\nd/w.txt,\n");
+        let files = ["a/x.txt", "b/x.txt", "c/y.txt", "d/w.txt"];
+        let (root, index) = index("structure", &files, &matcher);
+        let options = PlanOptions {
+            entries,
+            preserve_structure: true,
+            ..PlanOptions::default()
+        };
+        let plan = plan_matches(&matcher, &index, Path::new("/tgt"), &options).unwrap();
+        let targets: Vec<_> = plan
+            .copies
+            .iter()
+            .map(|copy| {
+                (
+                    copy.source.strip_prefix(&root).unwrap(),
+                    copy.target.as_path(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (Path::new("d/w.txt"), Path::new("/tgt/d/w.txt")),
+                (Path::new("a/x.txt"), Path::new("/tgt/first/a/x.txt")),
+                (Path::new("b/x.txt"), Path::new("/tgt/b/x.txt")),
+                (Path::new("c/y.txt"), Path::new("/tgt/out/c/z.txt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn preserved_structure_never_collides() {
+        let matcher = Matcher::new(&["x.txt"]).unwrap();
+        let (_root, index) = index("structure-collisions", &["a/x.txt", "b/x.txt"], &matcher);
+        let options = PlanOptions {
+            preserve_structure: true,
+            ..PlanOptions::default()
+        };
+        let plan = plan_matches(&matcher, &index, Path::new("/tgt"), &options).unwrap();
+        let targets: Vec<_> = plan
+            .copies
+            .iter()
+            .map(|copy| copy.target.as_path())
+            .collect();
+        assert_eq!(
+            targets,
+            [Path::new("/tgt/a/x.txt"), Path::new("/tgt/b/x.txt")]
+        );
+        assert!(plan.skipped.is_empty());
+    }
+}
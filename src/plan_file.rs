@@ -0,0 +1,183 @@
+//! Saving a plan to a file, so it can be reviewed and applied later.
+
+use crate::error::Error;
+use crate::plan::{Plan, PlannedCopy};
+use crate::verify::{hash_file, Checksum, HashAlgorithm};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// A plan and the file list it was made from, as saved by the `plan`
+/// subcommand.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanFile {
+    /// Entries of the file list, in list order.
+    pub entries: Vec<String>,
+    /// The plan made from them.
+    pub plan: Plan,
+}
+
+/// Writes `plan_file` to `path` as pretty-printed JSON.
+pub fn save_plan<P: AsRef<Path>>(path: P, plan_file: &PlanFile) -> Result<(), Error> {
+    let path = path.as_ref();
+    let file = File::create(path).map_err(|e| Error::io(path, e))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, plan_file)
+        .map_err(io::Error::from)
+        .and_then(|()| writeln!(writer))
+        .and_then(|()| writer.flush())
+        .map_err(|e| Error::io(path, e))
+}
+
+/// Reads a plan file written by [`save_plan`].
+pub fn load_plan<P: AsRef<Path>>(path: P) -> Result<PlanFile, Error> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| Error::io(path, e))?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| Error::io(path, e.into()))
+}
+
+/// Hashes the source of every copy in `plan` with `algorithm`, so that
+/// applying the plan later can tell if a source changed.
+pub fn hash_sources(plan: &mut Plan, algorithm: HashAlgorithm) -> Result<(), Error> {
+    for copy in &mut plan.copies {
+        let digest = hash_file(algorithm, &copy.source).map_err(|e| Error::io(&copy.source, e))?;
+        copy.checksum = Some(Checksum { algorithm, digest });
+    }
+    Ok(())
+}
+
+/// Checks that the source of `copy` still has the size, modification time
+/// and hash it had when the plan was made.
+pub(crate) fn check_source(copy: &PlannedCopy) -> Result<(), Error> {
+    let source = &copy.source;
+    let changed = || Err(Error::SourceChanged(source.clone()));
+    let metadata = match fs::metadata(source) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return changed(),
+        Err(e) => return Err(Error::io(source, e)),
+    };
+    if metadata.len() != copy.size {
+        return changed();
+    }
+    if copy.modified.is_some() && metadata.modified().ok() != copy.modified {
+        return changed();
+    }
+    if let Some(checksum) = &copy.checksum {
+        let digest = hash_file(checksum.algorithm, source).map_err(|e| Error::io(source, e))?;
+        if digest != checksum.digest {
+            return changed();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::{execute_plan, ExecuteOptions};
+    use crate::test_util::{planned_copy, TempDir};
+    use std::time::Duration;
+
+    /// A plan copying `src/x.txt`, holding "hello", with its size,
+    /// modification time and SHA-256 hash recorded.
+    fn plan(root: &TempDir) -> Plan {
+        let source = root.write("src/x.txt", "hello");
+        fs::create_dir_all(root.join("tgt")).unwrap();
+        let mut copy = planned_copy(&source, root.join("tgt/x.txt"));
+        let metadata = fs::metadata(&source).unwrap();
+        copy.size = metadata.len();
+        copy.modified = metadata.modified().ok();
+        let mut plan = Plan {
+            target_dir: root.join("tgt"),
+            copies: vec![copy],
+            matched: vec!["x.txt".to_string()],
+            ..Plan::default()
+        };
+        hash_sources(&mut plan, HashAlgorithm::Sha256).unwrap();
+        plan
+    }
+
+    /// Applies `plan`, returning the result of its only file.
+    fn apply(plan: &mut Plan) -> Result<(), Error> {
+        let options = ExecuteOptions {
+            dry_run: false,
+            jobs: 1,
+            check_sources: true,
+            ..ExecuteOptions::default()
+        };
+        let mut execution = execute_plan(plan, &options, |_, _| {}).unwrap();
+        execution.outcomes.remove(0).result.map(|_| ())
+    }
+
+    fn assert_changed(result: Result<(), Error>) {
+        assert!(
+            matches!(result, Err(Error::SourceChanged(_))),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn unchanged_sources_are_applied() {
+        let root = TempDir::new("plan-file-unchanged");
+        let mut plan = plan(&root);
+        apply(&mut plan).unwrap();
+        assert_eq!(fs::read_to_string(root.join("tgt/x.txt")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn sources_with_another_size_are_refused() {
+        let root = TempDir::new("plan-file-size");
+        let mut plan = plan(&root);
+        fs::write(&plan.copies[0].source, "hello!").unwrap();
+        assert_changed(apply(&mut plan));
+        assert!(!root.join("tgt/x.txt").exists());
+    }
+
+    #[test]
+    fn sources_modified_since_are_refused() {
+        let root = TempDir::new("plan-file-modified");
+        let mut plan = plan(&root);
+        let modified = plan.copies[0].modified.unwrap() + Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&plan.copies[0].source)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_changed(apply(&mut plan));
+    }
+
+    #[test]
+    fn sources_with_another_hash_are_refused() {
+        let root = TempDir::new("plan-file-hash");
+        let mut plan = plan(&root);
+        let source = plan.copies[0].source.clone();
+        let modified = plan.copies[0].modified.unwrap();
+        // Same size and time, other contents.
+        fs::write(&source, "HELLO").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_changed(apply(&mut plan));
+    }
+
+    #[test]
+    fn saved_plans_load_back_unchanged() {
+        let root = TempDir::new("plan-file-round-trip");
+        let mut plan = plan(&root);
+        plan.copies[0].expected_size = Some(5);
+        plan.unmatched = vec!["gone.txt".to_string()];
+        let plan_file = PlanFile {
+            entries: vec!["x.txt".to_string(), "gone.txt".to_string()],
+            plan,
+        };
+        let path = root.join("plan.json");
+        save_plan(&path, &plan_file).unwrap();
+        assert_eq!(load_plan(&path).unwrap(), plan_file);
+    }
+}
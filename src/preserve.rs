@@ -0,0 +1,215 @@
+//! Copying the attributes of a source file to its target.
+
+use std::fmt;
+use std::fs::{self, FileTimes};
+use std::io;
+use std::path::Path;
+
+/// A file attribute that can be carried over from a source to its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Attribute {
+    /// Extended attributes, other than ACLs.
+    Xattr,
+    /// POSIX access control lists.
+    Acl,
+    /// Owning user and group.
+    Owner,
+    /// Permission bits.
+    Mode,
+    /// Last access and modification times.
+    Times,
+}
+
+impl Attribute {
+    /// Every attribute, in the order they are applied.
+    pub const ALL: [Attribute; 5] = [
+        Attribute::Owner,
+        Attribute::Xattr,
+        Attribute::Acl,
+        Attribute::Mode,
+        Attribute::Times,
+    ];
+
+    /// Name of the attribute, as given on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Attribute::Xattr => "xattr",
+            Attribute::Acl => "acl",
+            Attribute::Owner => "owner",
+            Attribute::Mode => "mode",
+            Attribute::Times => "times",
+        }
+    }
+}
+
+/// An attribute that could not be carried over to a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unpreserved {
+    /// The attribute.
+    pub attribute: Attribute,
+    /// Why it could not be carried over.
+    pub reason: String,
+}
+
+impl fmt::Display for Unpreserved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.attribute.name(), self.reason)
+    }
+}
+
+/// Carries `attributes` over from `source` to `target`, returning those that
+/// could not be.
+///
+/// Attributes are applied in the order of [`Attribute::ALL`]. The owner is
+/// set first, as changing it clears file capabilities and set-id bits, which
+/// the extended attributes and mode then restore, and times are set last so
+/// that the others don't disturb them.
+pub fn preserve(source: &Path, target: &Path, attributes: &[Attribute]) -> Vec<Unpreserved> {
+    let metadata = match fs::metadata(source) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return attributes
+                .iter()
+                .map(|&attribute| Unpreserved {
+                    attribute,
+                    reason: e.to_string(),
+                })
+                .collect()
+        }
+    };
+
+    let mut failed = Vec::new();
+    for attribute in Attribute::ALL {
+        if !attributes.contains(&attribute) {
+            continue;
+        }
+        let result = match attribute {
+            Attribute::Xattr => copy_xattrs(source, target, |name| !is_acl(name)),
+            Attribute::Acl => copy_xattrs(source, target, is_acl),
+            Attribute::Owner => set_owner(target, &metadata),
+            Attribute::Mode => fs::set_permissions(target, metadata.permissions()),
+            Attribute::Times => set_times(target, &metadata),
+        };
+        if let Err(e) = result {
+            failed.push(Unpreserved {
+                attribute,
+                reason: e.to_string(),
+            });
+        }
+    }
+    failed
+}
+
+fn set_times(target: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    let mut times = FileTimes::new();
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    fs::File::open(target)?.set_times(times)
+}
+
+/// Whether the extended attribute `name` holds a POSIX ACL.
+fn is_acl(name: &str) -> bool {
+    name.starts_with("system.posix_acl_")
+}
+
+#[cfg(unix)]
+fn set_owner(target: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    std::os::unix::fs::chown(target, Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn set_owner(_target: &Path, _metadata: &fs::Metadata) -> io::Result<()> {
+    Err(unsupported())
+}
+
+/// Copies the extended attributes of `source` whose names are picked by
+/// `wanted` to `target`.
+#[cfg(unix)]
+fn copy_xattrs<F>(source: &Path, target: &Path, wanted: F) -> io::Result<()>
+where
+    F: Fn(&str) -> bool,
+{
+    for name in xattr::list(source)? {
+        if !wanted(&name.to_string_lossy()) {
+            continue;
+        }
+        if let Some(value) = xattr::get(source, &name)? {
+            xattr::set(target, &name, &value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs<F>(_source: &Path, _target: &Path, _wanted: F) -> io::Result<()>
+where
+    F: Fn(&str) -> bool,
+{
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn times_are_carried_over() {
+        let root = TempDir::new("preserve-times");
+        let source = root.write("a.txt", "hello");
+        let target = root.write("b.txt", "hello");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let times = FileTimes::new()
+            .set_accessed(modified)
+            .set_modified(modified);
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+
+        assert_eq!(preserve(&source, &target, &[Attribute::Times]), []);
+        let metadata = fs::metadata(&target).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        assert_eq!(metadata.accessed().unwrap(), modified);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_is_carried_over() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new("preserve-mode");
+        let source = root.write("a.txt", "hello");
+        let target = root.write("b.txt", "hello");
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+
+        assert_eq!(preserve(&source, &target, &[Attribute::Mode]), []);
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn attributes_of_a_missing_source_are_reported() {
+        let root = TempDir::new("preserve-missing");
+        let target = root.write("b.txt", "hello");
+        let unpreserved = preserve(&root.join("a.txt"), &target, &[Attribute::Times]);
+        assert_eq!(unpreserved.len(), 1);
+        assert_eq!(unpreserved[0].attribute, Attribute::Times);
+    }
+}
@@ -0,0 +1,458 @@
+//! Machine-readable reports of a run.
+
+use crate::execute::Outcome;
+use crate::existing::Action;
+use crate::index::{SourceFile, SourceIndex};
+use crate::matcher::Matcher;
+use crate::plan::{Plan, SkipReason};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// File format of a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ReportFormat {
+    /// A single JSON array.
+    Json,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+/// Whether a record describes a list entry or a source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordKind {
+    /// A line of the file list.
+    Entry,
+    /// A file in the source directory matched by the list.
+    File,
+}
+
+/// What happened to a list entry or a source file.
+///
+/// Statuses are ordered from best to worst, and a list entry takes the worst
+/// status of the files it matched, leaving out files that lost a collision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    /// The file was put in place.
+    Copied,
+    /// The file would have been put in place, had this not been a dry run.
+    WouldCopy,
+    /// The file was not put in place, because the run stopped first or a
+    /// file already in the target was kept.
+    Skipped,
+    /// The file shares its name with another file that was picked instead,
+    /// or with another file when collisions are not allowed.
+    Collision,
+    /// The list entry did not match any file.
+    Missing,
+    /// Putting the file in place failed.
+    Error,
+}
+
+/// One row of a report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    /// Whether this describes a list entry or a source file.
+    pub kind: RecordKind,
+    /// What happened.
+    pub status: Status,
+    /// The list entry, or the entry that matched the file.
+    pub entry: Option<String>,
+    /// Full path of the source file.
+    pub source: Option<PathBuf>,
+    /// Full path of the target file.
+    pub target: Option<PathBuf>,
+    /// What was done to put the file in place.
+    pub action: Option<Action>,
+    /// Size in bytes of the file, or of every file matched by the entry.
+    pub size: Option<u64>,
+    /// How long putting the file in place took, in milliseconds.
+    pub duration_ms: Option<f64>,
+    /// Why putting the file in place failed.
+    pub error: Option<String>,
+    /// Attributes that could not be carried over from the source, and why.
+    pub unpreserved: Option<String>,
+}
+
+/// Builds the records for a run: one per list entry, in list order, followed
+/// by one per matched source file.
+///
+/// `outcomes` are those returned by [`crate::execute_plan`], and `dry_run`
+/// whether that was a dry run.
+pub fn build_records(
+    entries: &[String],
+    plan: &Plan,
+    outcomes: &[Outcome],
+    dry_run: bool,
+) -> Vec<Record> {
+    let outcomes: HashMap<usize, &Outcome> = outcomes.iter().map(|o| (o.index, o)).collect();
+
+    let mut files = Vec::new();
+    for (index, copy) in plan.copies.iter().enumerate() {
+        let outcome = outcomes.get(&index);
+        let transferred = outcome.and_then(|o| o.result.as_ref().ok());
+        let status = match outcome.map(|o| &o.result) {
+            _ if dry_run => Status::WouldCopy,
+            Some(Ok(t)) if t.action == Action::Skipped => Status::Skipped,
+            Some(Ok(_)) => Status::Copied,
+            Some(Err(_)) => Status::Error,
+            None => Status::Skipped,
+        };
+        files.push(Record {
+            kind: RecordKind::File,
+            status,
+            entry: Some(copy.entry.clone()),
+            source: Some(copy.source.clone()),
+            target: Some(transferred.map_or(&copy.target, |t| &t.target).clone()),
+            action: transferred.map(|t| t.action),
+            size: Some(copy.size),
+            duration_ms: outcome.map(|o| o.duration.as_secs_f64() * 1000.0),
+            error: outcome.and_then(|o| o.result.as_ref().err().map(|e| e.to_string())),
+            unpreserved: transferred.filter(|t| !t.unpreserved.is_empty()).map(|t| {
+                let unpreserved: Vec<String> =
+                    t.unpreserved.iter().map(|u| u.to_string()).collect();
+                unpreserved.join("; ")
+            }),
+        });
+    }
+    for skipped in &plan.skipped {
+        files.push(Record {
+            kind: RecordKind::File,
+            status: match skipped.reason {
+                SkipReason::Collision => Status::Collision,
+            },
+            entry: Some(skipped.entry.clone()),
+            source: Some(skipped.source.clone()),
+            target: None,
+            action: None,
+            size: Some(skipped.size),
+            duration_ms: None,
+            error: None,
+            unpreserved: None,
+        });
+    }
+    files.sort_by(|a, b| a.source.cmp(&b.source));
+
+    // An entry that only matched files already claimed by an earlier entry
+    // has no files of its own, and did as well as a plain copy.
+    let handled = if dry_run {
+        Status::WouldCopy
+    } else {
+        Status::Copied
+    };
+    let mut by_entry: HashMap<&str, Vec<&Record>> = HashMap::new();
+    for file in &files {
+        if let Some(entry) = file.entry.as_deref() {
+            by_entry.entry(entry).or_default().push(file);
+        }
+    }
+    let unmatched: HashSet<&str> = plan.unmatched.iter().map(String::as_str).collect();
+    let mut records: Vec<Record> = entries
+        .iter()
+        .map(|entry| {
+            // Files that lost a collision don't count against an entry whose
+            // other files were handled.
+            let (lost, own): (Vec<&Record>, Vec<&Record>) = by_entry
+                .get(entry.as_str())
+                .into_iter()
+                .flatten()
+                .partition(|f| f.status == Status::Collision);
+            let status = if unmatched.contains(entry.as_str()) {
+                Status::Missing
+            } else if own.is_empty() && !lost.is_empty() {
+                Status::Collision
+            } else {
+                own.iter().map(|f| f.status).max().unwrap_or(handled)
+            };
+            entry_record(entry, status, own.iter().filter_map(|f| f.size).sum())
+        })
+        .collect();
+    records.extend(files);
+    records
+}
+
+/// Builds the records for a run that stopped because the listed file
+/// `names` were shared by several source files.
+///
+/// Entries matching one of those names are marked as collisions, and every
+/// other entry as skipped.
+pub fn collision_records(matcher: &Matcher, index: &SourceIndex, names: &[String]) -> Vec<Record> {
+    let files: Vec<_> = names
+        .iter()
+        .flat_map(|name| index.get(name))
+        .map(|file| (file, None))
+        .collect();
+    colliding_records(matcher, &files)
+}
+
+/// Builds the records for a run that stopped because several planned
+/// copies shared a target, given as found by [`plan_matches`].
+///
+/// Entries matching one of the files that would have been written to a
+/// shared target are marked as collisions, and every other entry as
+/// skipped.
+///
+/// [`plan_matches`]: crate::plan::plan_matches
+pub fn shared_target_records(
+    matcher: &Matcher,
+    index: &SourceIndex,
+    targets: &[(PathBuf, Vec<PathBuf>)],
+) -> Vec<Record> {
+    let mut files = Vec::new();
+    for (target, sources) in targets {
+        for source in sources {
+            let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+            let found = index
+                .get(&file_name)
+                .iter()
+                .chain(index.get_unmatched(&file_name))
+                .find(|file| &file.path == source);
+            files.extend(found.map(|file| (file, Some(target.as_path()))));
+        }
+    }
+    colliding_records(matcher, &files)
+}
+
+/// Builds the records for a run that stopped on colliding `files`, each
+/// with the target it would have been written to, when known.
+fn colliding_records(matcher: &Matcher, files: &[(&SourceFile, Option<&Path>)]) -> Vec<Record> {
+    let mut colliding = vec![false; matcher.entries().len()];
+    for (file, _) in files {
+        for i in matcher.matching_entries_for(file) {
+            colliding[i] = true;
+        }
+    }
+    let mut records: Vec<Record> = matcher
+        .entries()
+        .iter()
+        .zip(colliding)
+        .map(|(entry, colliding)| {
+            let status = if colliding {
+                Status::Collision
+            } else {
+                Status::Skipped
+            };
+            entry_record(entry, status, 0)
+        })
+        .collect();
+    for (file, target) in files {
+        let entries = matcher.matching_entries_for(file);
+        records.push(Record {
+            kind: RecordKind::File,
+            status: Status::Collision,
+            entry: entries.first().map(|&i| matcher.entries()[i].clone()),
+            source: Some(file.path.clone()),
+            target: target.map(Path::to_path_buf),
+            action: None,
+            size: Some(file.size),
+            duration_ms: None,
+            error: None,
+            unpreserved: None,
+        });
+    }
+    records
+}
+
+fn entry_record(entry: &str, status: Status, size: u64) -> Record {
+    Record {
+        kind: RecordKind::Entry,
+        status,
+        entry: Some(entry.to_string()),
+        source: None,
+        target: None,
+        action: None,
+        size: Some(size),
+        duration_ms: None,
+        error: None,
+        unpreserved: None,
+    }
+}
+
+/// Writes `records` to `writer` in the given format.
+pub fn write_report<W: Write>(
+    writer: W,
+    format: ReportFormat,
+    records: &[Record],
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Json => {
+            let mut writer = writer;
+            serde_json::to_writer_pretty(&mut writer, records)?;
+            writeln!(writer)
+        }
+        ReportFormat::Ndjson => {
+            let mut writer = writer;
+            for record in records {
+                serde_json::to_writer(&mut writer, record)?;
+                writeln!(writer)?;
+            }
+            Ok(())
+        }
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::execute::Transferred;
+    use crate::plan::{PlannedCopy, SkippedFile};
+    use crate::test_util::planned_copy;
+    use std::time::Duration;
+
+    /// A copy of `/src/{source}` matched by `entry`, of `size` bytes.
+    fn copy(source: &str, entry: &str, size: u64) -> PlannedCopy {
+        let mut copy = planned_copy(format!("/src/{}", source), format!("/tgt/{}", source));
+        copy.entry = entry.to_string();
+        copy.size = size;
+        copy
+    }
+
+    /// A file matched by `entry` that lost a collision.
+    fn lost(source: &str, entry: &str) -> SkippedFile {
+        SkippedFile {
+            file_name: source.rsplit('/').next().unwrap().to_string(),
+            entry: entry.to_string(),
+            source: PathBuf::from(format!("/src/{}", source)),
+            size: 100,
+            reason: SkipReason::Collision,
+        }
+    }
+
+    fn outcome(index: usize, result: Result<Transferred, Error>) -> Outcome {
+        Outcome {
+            index,
+            result,
+            duration: Duration::from_millis(1),
+        }
+    }
+
+    fn created(copy: &PlannedCopy) -> Result<Transferred, Error> {
+        Ok(Transferred {
+            target: copy.target.clone(),
+            hash: None,
+            action: Action::Created,
+            unpreserved: Vec::new(),
+        })
+    }
+
+    /// The status of every entry record, then of every file record, with
+    /// the entry or source it is for.
+    fn statuses(records: &[Record]) -> Vec<(String, Status)> {
+        records
+            .iter()
+            .map(|record| {
+                let name = match record.kind {
+                    RecordKind::Entry => record.entry.clone().unwrap(),
+                    RecordKind::File => record.source.as_ref().unwrap().display().to_string(),
+                };
+                (name, record.status)
+            })
+            .collect()
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn dry_runs_would_copy_every_planned_file() {
+        let entries = strings(&["a.txt", "dup.txt", "gone.txt"]);
+        let plan = Plan {
+            copies: vec![copy("a.txt", "a.txt", 1), copy("x/dup.txt", "dup.txt", 2)],
+            skipped: vec![lost("y/dup.txt", "dup.txt")],
+            matched: strings(&["a.txt", "dup.txt"]),
+            unmatched: strings(&["gone.txt"]),
+            ..Plan::default()
+        };
+        let records = build_records(&entries, &plan, &[], true);
+        assert_eq!(
+            statuses(&records),
+            [
+                ("a.txt".to_string(), Status::WouldCopy),
+                ("dup.txt".to_string(), Status::WouldCopy),
+                ("gone.txt".to_string(), Status::Missing),
+                ("/src/a.txt".to_string(), Status::WouldCopy),
+                ("/src/x/dup.txt".to_string(), Status::WouldCopy),
+                ("/src/y/dup.txt".to_string(), Status::Collision),
+            ]
+        );
+        // The file that lost the collision is left out of the entry's size.
+        assert_eq!(records[1].size, Some(2));
+    }
+
+    #[test]
+    fn runs_stopped_by_a_failure_skip_the_files_not_started() {
+        let entries = strings(&["a.txt", "b.txt", "c.txt", "?.txt"]);
+        let plan = Plan {
+            copies: vec![
+                copy("a.txt", "a.txt", 1),
+                copy("b.txt", "b.txt", 1),
+                copy("c.txt", "c.txt", 1),
+            ],
+            matched: entries.clone(),
+            ..Plan::default()
+        };
+        let outcomes = [
+            outcome(0, created(&plan.copies[0])),
+            outcome(1, Err(Error::TargetExists(PathBuf::from("/tgt/b.txt")))),
+        ];
+        let records = build_records(&entries, &plan, &outcomes, false);
+        assert_eq!(
+            statuses(&records),
+            [
+                ("a.txt".to_string(), Status::Copied),
+                ("b.txt".to_string(), Status::Error),
+                ("c.txt".to_string(), Status::Skipped),
+                // Matched only files claimed by the entries before it.
+                ("?.txt".to_string(), Status::Copied),
+                ("/src/a.txt".to_string(), Status::Copied),
+                ("/src/b.txt".to_string(), Status::Error),
+                ("/src/c.txt".to_string(), Status::Skipped),
+            ]
+        );
+        assert_eq!(records[4].action, Some(Action::Created));
+        assert_eq!(
+            records[5].error.as_deref(),
+            Some("`/tgt/b.txt` already exists")
+        );
+        assert_eq!(records[6].action, None);
+        assert_eq!(records[6].duration_ms, None);
+    }
+
+    #[test]
+    fn entries_whose_files_all_lost_a_collision_are_collisions() {
+        let entries = strings(&["dup.txt", "y/dup.txt"]);
+        let plan = Plan {
+            copies: vec![copy("x/dup.txt", "dup.txt", 2)],
+            skipped: vec![lost("y/dup.txt", "y/dup.txt")],
+            matched: entries.clone(),
+            ..Plan::default()
+        };
+        let outcomes = [outcome(0, created(&plan.copies[0]))];
+        let records = build_records(&entries, &plan, &outcomes, false);
+        assert_eq!(
+            statuses(&records),
+            [
+                ("dup.txt".to_string(), Status::Copied),
+                ("y/dup.txt".to_string(), Status::Collision),
+                ("/src/x/dup.txt".to_string(), Status::Copied),
+                ("/src/y/dup.txt".to_string(), Status::Collision),
+            ]
+        );
+        assert_eq!(records[1].size, Some(0));
+    }
+}
@@ -0,0 +1,680 @@
+//! Suggesting file names for list entries that matched nothing.
+
+use crate::index::SourceIndex;
+use crate::matcher::{is_valid_glob, Matcher, REGEX_PREFIX};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::thread;
+
+/// Options that change which names are suggested.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuggestOptions {
+    /// Lowest similarity, from 0 to 1, for a name to be suggested.
+    pub min_score: f64,
+    /// Most names suggested for each entry.
+    pub limit: usize,
+    /// Number of threads comparing names. `0` uses one per CPU.
+    pub jobs: usize,
+}
+
+impl Default for SuggestOptions {
+    fn default() -> SuggestOptions {
+        SuggestOptions {
+            min_score: 0.75,
+            limit: 3,
+            jobs: 0,
+        }
+    }
+}
+
+/// A file name in the source directory that is close to a list entry that
+/// matched nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    /// The list entry, as written.
+    pub entry: String,
+    /// Position of the entry in the list.
+    pub position: usize,
+    /// The suggested file name.
+    pub file_name: String,
+    /// How similar the name is to the entry, from 0 to 1.
+    pub score: f64,
+}
+
+/// Number of buckets characters are counted in.
+const BUCKETS: usize = 64;
+
+/// A name brought into the form names are compared in.
+struct Key {
+    /// The name, lower-cased, with its tokens joined by single spaces.
+    chars: Vec<char>,
+    /// Ids of the distinct tokens of the name, sorted.
+    tokens: Vec<u32>,
+    /// How many characters of the name fall in each bucket.
+    counts: [u8; BUCKETS],
+    /// Which buckets hold any character of the name, one bit each.
+    buckets: u64,
+}
+
+/// Hands out an id for every distinct token, so token sets can be compared
+/// as sorted lists of numbers.
+#[derive(Default)]
+struct Tokens(HashMap<String, u32>);
+
+impl Tokens {
+    /// Lower-cases `name` and turns every run of `_`, `-`, `.` and
+    /// whitespace into a single space, so names that only differ in case or
+    /// separators are equal.
+    fn key(&mut self, name: &str) -> Key {
+        let lower = name.to_lowercase();
+        let words: Vec<&str> = lower
+            .split(|c: char| c == '_' || c == '-' || c == '.' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .collect();
+        let mut tokens: Vec<u32> = words
+            .iter()
+            .map(|&word| {
+                let next = self.0.len() as u32;
+                *self.0.entry(word.to_string()).or_insert(next)
+            })
+            .collect();
+        tokens.sort_unstable();
+        tokens.dedup();
+        let chars: Vec<char> = words.join(" ").chars().collect();
+        let mut counts = [0u8; BUCKETS];
+        let mut buckets = 0;
+        for &c in &chars {
+            let bucket = bucket(c);
+            counts[bucket] = counts[bucket].saturating_add(1);
+            buckets |= 1 << bucket;
+        }
+        Key {
+            chars,
+            tokens,
+            counts,
+            buckets,
+        }
+    }
+}
+
+impl Key {
+    /// How similar two keys are, from 0 to 1, if that is at least `floor`:
+    /// the larger of their edit distance similarity and the share of tokens
+    /// they have in common, so that typos and reordered words both score
+    /// high.
+    ///
+    /// The edit distance is only worked out when cheaper bounds allow it to
+    /// beat both `floor` and the token score, and only up to the distance
+    /// that would still do so. `rows` is scratch space.
+    fn similarity(&self, other: &Key, floor: f64, rows: &mut Rows) -> Option<f64> {
+        let shared = count_shared(&self.tokens, &other.tokens);
+        let union = self.tokens.len() + other.tokens.len() - shared;
+        let tokens = match union {
+            0 => 0.0,
+            union => shared as f64 / union as f64,
+        };
+        let longest = self.chars.len().max(other.chars.len());
+        let shortest = self.chars.len().min(other.chars.len());
+        if longest == 0 {
+            return (floor <= 1.0).then_some(1.0);
+        }
+        // Each edit fixes at most one character, so the difference in length
+        // caps how similar the keys can be.
+        let floor = tokens.max(floor);
+        let bound = shortest as f64 / longest as f64;
+        if bound >= floor && bound > tokens {
+            if let Some(score) = self.edit_similarity(other, floor, rows) {
+                return Some(score.max(tokens));
+            }
+        }
+        (tokens >= floor).then_some(tokens)
+    }
+
+    /// The edit distance similarity of two keys, from 0 to 1, if that is at
+    /// least `floor`.
+    fn edit_similarity(&self, other: &Key, floor: f64, rows: &mut Rows) -> Option<f64> {
+        let longest = self.chars.len().max(other.chars.len());
+        if longest == 0 {
+            return (floor <= 1.0).then_some(1.0);
+        }
+        let most = ((1.0 - floor) * longest as f64 + 1e-9).floor() as usize;
+        if self.edits_at_least(other) > most {
+            return None;
+        }
+        let distance = rows.levenshtein(&self.chars, &other.chars, most)?;
+        // Worked out as a single division, like the other scores and bounds,
+        // so that equal ratios compare equal.
+        Some((longest - distance) as f64 / longest as f64)
+    }
+
+    /// A quick lower bound on the edit distance between two keys: every
+    /// bucket used by only one of them takes an edit of its own.
+    fn buckets_apart(&self, buckets: u64) -> usize {
+        let missing = (self.buckets & !buckets).count_ones();
+        let added = (buckets & !self.buckets).count_ones();
+        missing.max(added) as usize
+    }
+
+    /// A lower bound on the edit distance between two keys: every edit adds
+    /// or removes at most one character of each bucket, and insertions and
+    /// deletions also change the length.
+    fn edits_at_least(&self, other: &Key) -> usize {
+        let apart: u32 = self
+            .counts
+            .iter()
+            .zip(&other.counts)
+            .map(|(&a, &b)| u32::from(a.abs_diff(b)))
+            .sum();
+        (apart as usize + self.chars.len().abs_diff(other.chars.len())) / 2
+    }
+}
+
+/// The bucket `c` is counted in. Letters, digits and spaces each get their
+/// own, as they make up most names.
+fn bucket(c: char) -> usize {
+    match c {
+        'a'..='z' => c as usize - 'a' as usize,
+        '0'..='9' => 26 + (c as usize - '0' as usize),
+        ' ' => 36,
+        _ => 37 + c as usize % (BUCKETS - 37),
+    }
+}
+
+/// Counts the numbers found in both sorted lists.
+fn count_shared(a: &[u32], b: &[u32]) -> usize {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    shared
+}
+
+/// The two rows of the edit distance table, kept between comparisons.
+#[derive(Default)]
+struct Rows(Vec<usize>, Vec<usize>);
+
+impl Rows {
+    /// Works out the Levenshtein distance between `a` and `b`, giving up as
+    /// soon as it is sure to be more than `most`.
+    fn levenshtein(&mut self, a: &[char], b: &[char], most: usize) -> Option<usize> {
+        if a.len().abs_diff(b.len()) > most {
+            return None;
+        }
+        let Rows(previous, current) = self;
+        previous.clear();
+        previous.extend(0..=b.len());
+        current.clear();
+        current.resize(b.len() + 1, 0);
+        for (i, &x) in a.iter().enumerate() {
+            current[0] = i + 1;
+            let mut lowest = current[0];
+            for (j, &y) in b.iter().enumerate() {
+                let substitute = previous[j] + usize::from(x != y);
+                current[j + 1] = substitute.min(previous[j + 1] + 1).min(current[j] + 1);
+                lowest = lowest.min(current[j + 1]);
+            }
+            if lowest > most {
+                return None;
+            }
+            std::mem::swap(previous, current);
+        }
+        let distance = previous[b.len()];
+        (distance <= most).then_some(distance)
+    }
+}
+
+/// The names of the indexed files, with their keys sorted by length and
+/// looked up by token.
+struct Candidates<'a> {
+    names: Vec<&'a str>,
+    /// Keys of the names, whole and without their extension, each with the
+    /// position of its name. Sorted by length.
+    keys: Vec<(Key, usize)>,
+    /// Length and buckets of each key in `keys`, kept apart so they can be
+    /// scanned quickly.
+    sizes: Vec<(usize, u64)>,
+    /// Positions in `keys` of the keys holding each token.
+    by_token: HashMap<u32, Vec<usize>>,
+}
+
+impl<'a> Candidates<'a> {
+    fn new(index: &'a SourceIndex, tokens: &mut Tokens) -> Candidates<'a> {
+        let mut names: Vec<&str> = index
+            .iter()
+            .chain(index.unmatched())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        let mut keys = Vec::new();
+        for (i, &name) in names.iter().enumerate() {
+            let whole = tokens.key(name);
+            if let Some((stem, _)) = name.rsplit_once('.').filter(|(stem, _)| !stem.is_empty()) {
+                let stem = tokens.key(stem);
+                if stem.chars != whole.chars {
+                    keys.push((stem, i));
+                }
+            }
+            keys.push((whole, i));
+        }
+        keys.sort_by_key(|(key, _)| key.chars.len());
+        let mut by_token: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (k, (key, _)) in keys.iter().enumerate() {
+            for &token in &key.tokens {
+                by_token.entry(token).or_default().push(k);
+            }
+        }
+        let sizes = keys
+            .iter()
+            .map(|(key, _)| (key.chars.len(), key.buckets))
+            .collect();
+        Candidates {
+            names,
+            keys,
+            sizes,
+            by_token,
+        }
+    }
+
+    /// Finds the `limit` names closest to `key` that score at least
+    /// `min_score`, best first, and names in order on a tie.
+    ///
+    /// Only keys that can reach `min_score` are compared: those close
+    /// enough in length for the edit distance to, and those holding one of
+    /// the rarest tokens of `key` for the shared tokens to. `seen` is
+    /// scratch space with a slot for every key.
+    fn closest(
+        &self,
+        key: &Key,
+        min_score: f64,
+        limit: usize,
+        rows: &mut Rows,
+        seen: &mut [bool],
+    ) -> Vec<(f64, usize)> {
+        let mut best: Vec<(f64, usize)> = Vec::new();
+        // Once `limit` names are found, only as close ones can join them.
+        let floor = |best: &[(f64, usize)]| match best.get(limit - 1) {
+            Some(&(score, _)) => score.max(min_score),
+            None => min_score,
+        };
+        let keep = |best: &mut Vec<(f64, usize)>, score: f64, name: usize| {
+            match best.iter_mut().find(|(_, n)| *n == name) {
+                Some(found) if found.0 >= score => return,
+                Some(found) => found.0 = score,
+                None => best.push((score, name)),
+            }
+            best.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+            best.truncate(limit);
+        };
+
+        let everything = min_score <= 0.0;
+        let mut visited = Vec::new();
+        if !everything && !key.tokens.is_empty() {
+            // Sharing a share `min_score` of the tokens of both names needs
+            // at least that share of the tokens of `key`, so a name has to
+            // hold one of the rarest tokens left after that many.
+            let needed = (min_score * key.tokens.len() as f64 - 1e-9).ceil() as usize;
+            let mut postings: Vec<&[usize]> = key
+                .tokens
+                .iter()
+                .map(|token| self.by_token.get(token).map_or(&[][..], Vec::as_slice))
+                .collect();
+            postings.sort_by_key(|posting| posting.len());
+            for posting in postings
+                .into_iter()
+                .take(key.tokens.len() + 1 - needed.max(1))
+            {
+                for &k in posting {
+                    if !seen[k] {
+                        seen[k] = true;
+                        visited.push(k);
+                        let (other, name) = &self.keys[k];
+                        if let Some(score) = key.similarity(other, floor(&best), rows) {
+                            keep(&mut best, score, *name);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The keys left share too few tokens to score on them, so only their
+        // edit distance counts, which needs lengths at least `min_score`'s
+        // share of each other.
+        if everything {
+            for k in (0..self.keys.len()).filter(|&k| !seen[k]) {
+                let (other, name) = &self.keys[k];
+                if let Some(score) = key.similarity(other, floor(&best), rows) {
+                    keep(&mut best, score, *name);
+                }
+            }
+        } else {
+            let length = key.chars.len() as f64;
+            let shortest = (length * min_score - 1e-9).ceil() as usize;
+            let longest = (length / min_score + 1e-9).floor() as usize;
+            let low = self.sizes.partition_point(|&(size, _)| size < shortest);
+            let high = self.sizes.partition_point(|&(size, _)| size <= longest);
+            let mut lowest = floor(&best);
+            for k in (low..high).filter(|&k| !seen[k]) {
+                let (size, buckets) = self.sizes[k];
+                let most = (1.0 - lowest) * size.max(key.chars.len()) as f64 + 1e-9;
+                if key.buckets_apart(buckets) as f64 > most {
+                    continue;
+                }
+                let (other, name) = &self.keys[k];
+                if let Some(score) = key.edit_similarity(other, lowest, rows) {
+                    keep(&mut best, score, *name);
+                    lowest = floor(&best);
+                }
+            }
+        }
+        for k in visited {
+            seen[k] = false;
+        }
+        best
+    }
+}
+
+/// Suggests, for every entry in `unmatched`, the names of the indexed files
+/// closest to it. Suggestions are in list order, and best first for each
+/// entry.
+///
+/// Names are compared without regard to case or separators, both whole and
+/// without their extension, so an entry missing its extension or cut short
+/// still finds its file. Path entries are compared by their last part, and
+/// globs and regular expressions get no suggestions. The index has to keep
+/// unmatched files for their names to be suggested.
+///
+/// The entries are shared out between [`SuggestOptions::jobs`] threads.
+pub fn suggest(
+    matcher: &Matcher,
+    unmatched: &[String],
+    index: &SourceIndex,
+    options: &SuggestOptions,
+) -> Vec<Suggestion> {
+    if options.limit == 0 {
+        return Vec::new();
+    }
+    let mut tokens = Tokens::default();
+    let candidates = Candidates::new(index, &mut tokens);
+
+    // Patterns are left out, as they are not spelled like a file name.
+    let unmatched: HashSet<&str> = unmatched.iter().map(String::as_str).collect();
+    let entries: Vec<(usize, &String, Key)> = matcher
+        .entries()
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            !entry.starts_with(REGEX_PREFIX)
+                && !is_valid_glob(entry)
+                && unmatched.contains(entry.as_str())
+        })
+        .map(|(position, entry)| {
+            let key = tokens.key(entry.rsplit('/').next().unwrap_or(entry));
+            (position, entry, key)
+        })
+        .collect();
+
+    let suggest_for = |entries: &[(usize, &String, Key)]| {
+        let mut rows = Rows::default();
+        let mut seen = vec![false; candidates.keys.len()];
+        let mut suggestions = Vec::new();
+        for (position, entry, key) in entries {
+            let closest =
+                candidates.closest(key, options.min_score, options.limit, &mut rows, &mut seen);
+            for (score, name) in closest {
+                suggestions.push(Suggestion {
+                    entry: (*entry).clone(),
+                    position: *position,
+                    file_name: candidates.names[name].to_string(),
+                    score,
+                });
+            }
+        }
+        suggestions
+    };
+
+    let jobs = match options.jobs {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        jobs => jobs,
+    };
+    let chunk = entries.len().div_ceil(jobs).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = entries
+            .chunks(chunk)
+            .map(|entries| scope.spawn(|| suggest_for(entries)))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// Picks the suggestions that can be taken as matches: the best one for an
+/// entry, when it scores at least `confidence`, scores higher than the next
+/// one and names a single file. Returns the full path of each such file,
+/// with the position of its entry.
+pub fn accept_suggestions(
+    suggestions: &[Suggestion],
+    index: &SourceIndex,
+    confidence: f64,
+) -> Vec<(PathBuf, usize)> {
+    let mut accepted = Vec::new();
+    for (i, best) in suggestions.iter().enumerate() {
+        let first = i == 0 || suggestions[i - 1].position != best.position;
+        if !first || best.score < confidence {
+            continue;
+        }
+        let tied = suggestions
+            .get(i + 1)
+            .is_some_and(|next| next.position == best.position && next.score >= best.score);
+        let files: Vec<_> = index
+            .get(&best.file_name)
+            .iter()
+            .chain(index.get_unmatched(&best.file_name))
+            .collect();
+        if let [file] = files.as_slice() {
+            if !tied {
+                accepted.push((file.path.clone(), best.position));
+            }
+        }
+    }
+    accepted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{index_source, IndexOptions};
+    use crate::test_util::TempDir;
+
+    /// Indexes `files`, created below a new directory for the test called
+    /// `name`, keeping every file as unmatched.
+    fn index(name: &str, files: &[String]) -> (TempDir, SourceIndex) {
+        let root = TempDir::new(&format!("suggest-{}", name));
+        for file in files {
+            root.write(file, "");
+        }
+        let matcher = Matcher::new(&[] as &[&str]).unwrap();
+        let options = IndexOptions {
+            jobs: 1,
+            keep_unmatched: true,
+            ..IndexOptions::default()
+        };
+        let index = index_source(&root, &matcher, &options).unwrap();
+        (root, index)
+    }
+
+    /// A small generator of pseudo-random numbers, so the names are the same
+    /// on every run.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % n
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+
+        /// A name made of a few words, separators and an extension.
+        fn name(&mut self) -> String {
+            let words = ["report", "scan", "img", "2023", "final", "draft", "a", "b7"];
+            let mut name = String::new();
+            for i in 0..1 + self.below(4) {
+                if i > 0 {
+                    name.push_str(self.pick(&["_", "-", " ", ""]));
+                }
+                name.push_str(self.pick(&words));
+            }
+            name.push_str(self.pick(&[".pdf", ".tif", ".JPG", ""]));
+            name
+        }
+
+        /// `name` with a few characters replaced, added or removed.
+        fn typo(&mut self, name: &str) -> String {
+            let mut chars: Vec<char> = name.chars().collect();
+            for _ in 0..self.below(3) {
+                let at = self.below(chars.len() + 1);
+                let c = self.pick(&["x", "E", "1", "_"]).chars().next().unwrap();
+                match self.below(3) {
+                    0 if at < chars.len() => chars[at] = c,
+                    1 if at < chars.len() => {
+                        chars.remove(at);
+                    }
+                    _ => chars.insert(at, c),
+                }
+            }
+            chars.into_iter().collect()
+        }
+    }
+
+    fn levenshtein(a: &[char], b: &[char]) -> usize {
+        let mut previous: Vec<usize> = (0..=b.len()).collect();
+        for (i, &x) in a.iter().enumerate() {
+            let mut current = vec![i + 1];
+            for (j, &y) in b.iter().enumerate() {
+                let substitute = previous[j] + usize::from(x != y);
+                current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+            }
+            previous = current;
+        }
+        previous[b.len()]
+    }
+
+    /// The score of every name for `key`, worked out without any of the
+    /// shortcuts of [`Candidates::closest`].
+    fn brute_force(candidates: &Candidates, key: &Key) -> Vec<f64> {
+        let mut scores = vec![f64::NEG_INFINITY; candidates.names.len()];
+        for (other, name) in &candidates.keys {
+            let shared = count_shared(&key.tokens, &other.tokens);
+            let union = key.tokens.len() + other.tokens.len() - shared;
+            let tokens = if union == 0 {
+                0.0
+            } else {
+                shared as f64 / union as f64
+            };
+            let longest = key.chars.len().max(other.chars.len());
+            let edits = if longest == 0 {
+                1.0
+            } else {
+                (longest - levenshtein(&key.chars, &other.chars)) as f64 / longest as f64
+            };
+            scores[*name] = scores[*name].max(edits.max(tokens));
+        }
+        scores
+    }
+
+    /// The `limit` best of `scores` that are at least `min_score`, best
+    /// first and names in order on a tie.
+    fn best(scores: &[f64], min_score: f64, limit: usize) -> Vec<(f64, usize)> {
+        let mut best: Vec<(f64, usize)> = scores
+            .iter()
+            .enumerate()
+            .filter(|&(_, &score)| score >= min_score)
+            .map(|(name, &score)| (score, name))
+            .collect();
+        best.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        best.truncate(limit);
+        best
+    }
+
+    #[test]
+    fn closest_finds_the_same_names_as_brute_force() {
+        let mut random = Random(42);
+        let mut files: Vec<String> = (0..300).map(|_| random.name()).collect();
+        files.sort();
+        files.dedup();
+        files.retain(|name| !name.is_empty());
+        let (_root, index) = index("brute-force", &files);
+
+        let mut tokens = Tokens::default();
+        let candidates = Candidates::new(&index, &mut tokens);
+        let mut rows = Rows::default();
+        let mut seen = vec![false; candidates.keys.len()];
+        for _ in 0..100 {
+            let name = random.name();
+            let query = random.typo(&name);
+            let key = tokens.key(&query);
+            let scores = brute_force(&candidates, &key);
+            for min_score in [0.0, 0.4, 0.75, 0.9] {
+                for limit in [1, 3] {
+                    let closest = candidates.closest(&key, min_score, limit, &mut rows, &mut seen);
+                    let expected = best(&scores, min_score, limit);
+                    assert_eq!(
+                        closest, expected,
+                        "`{}` with min score {} and limit {}",
+                        query, min_score, limit
+                    );
+                }
+            }
+        }
+    }
+
+    fn suggestion(position: usize, file_name: &str, score: f64) -> Suggestion {
+        Suggestion {
+            entry: format!("entry-{}", position),
+            position,
+            file_name: file_name.to_string(),
+            score,
+        }
+    }
+
+    #[test]
+    fn only_clear_best_suggestions_of_single_files_are_accepted() {
+        let files = ["report.pdf", "draft.pdf", "a/scan.tif", "b/scan.tif"].map(String::from);
+        let (root, index) = index("accept", &files);
+        let suggestions = [
+            suggestion(0, "report.pdf", 0.9),
+            suggestion(0, "draft.pdf", 0.8),
+            // Tied with the next name.
+            suggestion(1, "report.pdf", 0.9),
+            suggestion(1, "draft.pdf", 0.9),
+            // Shared by two files.
+            suggestion(2, "scan.tif", 0.95),
+            suggestion(2, "draft.pdf", 0.8),
+            // Not close enough.
+            suggestion(3, "draft.pdf", 0.7),
+            suggestion(4, "draft.pdf", 0.85),
+        ];
+        let accepted = accept_suggestions(&suggestions, &index, 0.8);
+        assert_eq!(
+            accepted,
+            [(root.join("report.pdf"), 0), (root.join("draft.pdf"), 4)]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Fixtures shared by the tests.
+
+use crate::execute::{execute_plan, ExecuteOptions, Execution};
+use crate::journal::Journal;
+use crate::plan::{Plan, PlannedCopy};
+use crate::transfer::TransferMode;
+use crate::verify::HashAlgorithm;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A new, empty directory that is removed when dropped, even when the test
+/// fails.
+#[derive(Debug)]
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates a directory for the test called `name`, unique to this
+    /// process and call.
+    pub(crate) fn new(name: &str) -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("finder-{}-{}-{}", name, std::process::id(), n));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// Writes `contents` to `path` below the directory, creating its
+    /// folders, and returns its full path.
+    pub(crate) fn write(&self, path: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A copy of `source` to `target`, matched by an entry giving the file name
+/// of `source`, with no size, times or hashes recorded.
+pub(crate) fn planned_copy<S: AsRef<Path>, T: AsRef<Path>>(source: S, target: T) -> PlannedCopy {
+    let source = source.as_ref();
+    let file_name = source
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    PlannedCopy {
+        entry: file_name.clone(),
+        file_name,
+        source: source.to_path_buf(),
+        target: target.as_ref().to_path_buf(),
+        mode: TransferMode::Copy,
+        size: 0,
+        modified: None,
+        checksum: None,
+        expected_size: None,
+        expected_checksum: None,
+    }
+}
+
+/// Options that carry out a plan for real, one file at a time, recording it
+/// in `journal`.
+pub(crate) fn journaled(journal: Journal) -> ExecuteOptions {
+    ExecuteOptions {
+        dry_run: false,
+        jobs: 1,
+        journal: Some(Arc::new(journal)),
+        ..ExecuteOptions::default()
+    }
+}
+
+/// Carries out `plan`, recording it in a new SHA-256 journal at `path`.
+pub(crate) fn execute_journaled(plan: &mut Plan, path: &Path) -> Execution {
+    let journal = Journal::create(path, HashAlgorithm::Sha256).unwrap();
+    execute_plan(plan, &journaled(journal), |_, _| {}).unwrap()
+}
@@ -0,0 +1,402 @@
+//! Putting a single file in place.
+
+use crate::preserve::{preserve, Attribute, Unpreserved};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Start of the names of temporary files, which are copied to and then
+/// renamed into place. The rest of the name is the process ID and a counter,
+/// so it has the same length whatever the name of the target.
+pub const TEMP_PREFIX: &str = ".finder-tmp-";
+
+/// How a source file is put in place in the target directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferMode {
+    /// Copy the file.
+    #[default]
+    Copy,
+    /// Move the file, copying and then deleting it when the target is on
+    /// another device.
+    Move,
+    /// Create a hard link to the file.
+    Hardlink,
+    /// Create a symbolic link holding the absolute path of the file.
+    Symlink,
+    /// Create a symbolic link holding the path of the file relative to the
+    /// link.
+    SymlinkRelative,
+    /// Clone the file, sharing its blocks on a copy-on-write filesystem.
+    /// Fails where the filesystem does not support it.
+    Reflink,
+}
+
+impl TransferMode {
+    /// Describes the action, such as "copying", for progress messages.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            TransferMode::Copy => "copying",
+            TransferMode::Move => "moving",
+            TransferMode::Hardlink => "hard linking",
+            TransferMode::Symlink | TransferMode::SymlinkRelative => "symlinking",
+            TransferMode::Reflink => "reflinking",
+        }
+    }
+}
+
+/// Puts `source` in place at `target` using `mode`.
+///
+/// Copies and reflinks are written to a temporary file next to `target`,
+/// flushed to disk and then renamed, so `target` never holds a partial file.
+pub fn transfer(mode: TransferMode, source: &Path, target: &Path) -> io::Result<()> {
+    transfer_preserving(mode, source, target, &[]).map(|_| ())
+}
+
+/// Puts `source` in place at `target` using `mode`, like [`transfer`].
+///
+/// Copies, reflinks and moves that have to copy the file to another device
+/// are given the `attributes` of the source while they are still a
+/// temporary file, so `target` never holds a file without them, and those
+/// that could not be carried over are returned. Other modes return nothing,
+/// as a renamed file keeps its attributes and a link shares them.
+pub fn transfer_preserving(
+    mode: TransferMode,
+    source: &Path,
+    target: &Path,
+    attributes: &[Attribute],
+) -> io::Result<Vec<Unpreserved>> {
+    transfer_checked(mode, source, target, attributes, |_| Ok(true)).map(Option::unwrap_or_default)
+}
+
+/// Puts `source` in place at `target` like [`transfer_preserving`], calling
+/// `check` with the file written before it takes the place of `target`.
+///
+/// When `check` returns `false`, copies, reflinks and moves to another
+/// device are removed while still a temporary file, leaving `target` and
+/// the source as they were, and links are removed again. A move within a
+/// device is checked once renamed, and kept as it is the only copy.
+/// Returns the attributes that could not be carried over, or `None` when
+/// `check` rejected the file.
+pub fn transfer_checked<F>(
+    mode: TransferMode,
+    source: &Path,
+    target: &Path,
+    attributes: &[Attribute],
+    check: F,
+) -> io::Result<Option<Vec<Unpreserved>>>
+where
+    F: FnOnce(&Path) -> io::Result<bool>,
+{
+    let linked = |result: io::Result<()>, check: F| {
+        result?;
+        if check(target)? {
+            return Ok(Some(Vec::new()));
+        }
+        fs::remove_file(target)?;
+        Ok(None)
+    };
+    match mode {
+        TransferMode::Copy => write_atomically(target, |temp| {
+            fs::copy(source, temp)?;
+            let unpreserved = preserve(source, temp, attributes);
+            Ok(check(temp)?.then_some(unpreserved))
+        }),
+        TransferMode::Move => move_file(source, target, attributes, check),
+        TransferMode::Hardlink => linked(fs::hard_link(source, target), check),
+        TransferMode::Symlink => linked(symlink(source, target), check),
+        TransferMode::SymlinkRelative => {
+            let parent = target.parent().unwrap_or(Path::new(""));
+            let link = relative_path(&fs::canonicalize(parent)?, &resolve(source)?);
+            linked(symlink(&link, target), check)
+        }
+        TransferMode::Reflink => write_atomically(target, |temp| {
+            reflink(source, temp)?;
+            let unpreserved = preserve(source, temp, attributes);
+            Ok(check(temp)?.then_some(unpreserved))
+        }),
+    }
+}
+
+/// Finds the temporary files left below `dir` by runs that were stopped
+/// halfway through a copy.
+pub fn find_temp_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            } else if name.starts_with(TEMP_PREFIX) {
+                found.push(entry.path());
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Path of the temporary file that `target` is written to first, in the
+/// same directory. Its name is unique to the call and no longer than
+/// `.finder-tmp-` and two numbers, so any name that fits the directory
+/// can still be written.
+fn temp_path(target: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    target.with_file_name(format!("{}{}-{}", TEMP_PREFIX, std::process::id(), n))
+}
+
+/// Calls `write` to create the temporary file for `target`, then flushes
+/// it to disk and renames it into place, returning what `write` returned.
+/// The temporary file is removed instead if anything fails or `write`
+/// returns `None`.
+fn write_atomically<T, F>(target: &Path, write: F) -> io::Result<Option<T>>
+where
+    F: FnOnce(&Path) -> io::Result<Option<T>>,
+{
+    let temp = temp_path(target);
+    let result = write(&temp).and_then(|written| {
+        if written.is_some() {
+            fs::File::open(&temp)?.sync_all()?;
+            fs::rename(&temp, target)?;
+        }
+        Ok(written)
+    });
+    if !matches!(result, Ok(Some(_))) {
+        let _ = fs::remove_file(&temp);
+    }
+    let written = result?;
+    if written.is_some() {
+        sync_parent(target)?;
+    }
+    Ok(written)
+}
+
+/// Flushes the directory holding `path` to disk, so a rename into it
+/// survives a crash.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Renames `source` to `target`, falling back to copy and delete when they
+/// are on different devices. The copy is given the `attributes` of the
+/// source and passed to `check` before it is renamed into place, returning
+/// those that could not be carried over, and the source is only deleted if
+/// `check` accepted it.
+fn move_file<F>(
+    source: &Path,
+    target: &Path,
+    attributes: &[Attribute],
+    check: F,
+) -> io::Result<Option<Vec<Unpreserved>>>
+where
+    F: FnOnce(&Path) -> io::Result<bool>,
+{
+    match fs::rename(source, target) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let unpreserved = write_atomically(target, |temp| {
+                fs::copy(source, temp)?;
+                let unpreserved = preserve(source, temp, attributes);
+                Ok(check(temp)?.then_some(unpreserved))
+            })?;
+            if unpreserved.is_some() {
+                fs::remove_file(source)?;
+            }
+            Ok(unpreserved)
+        }
+        Err(e) => Err(e),
+        Ok(()) => Ok(check(target)?.then(Vec::new)),
+    }
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let source_file = fs::File::open(source)?;
+    let target_file = fs::File::create_new(target)?;
+    // SAFETY: both descriptors stay open for the duration of the call.
+    let result = unsafe {
+        libc::ioctl(
+            target_file.as_raw_fd(),
+            libc::FICLONE,
+            source_file.as_raw_fd(),
+        )
+    };
+    if result == -1 {
+        let error = io::Error::last_os_error();
+        drop(target_file);
+        let _ = fs::remove_file(target);
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _target: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are not supported on this platform",
+    ))
+}
+
+/// Resolves `..`, `.` and symbolic links in the folders of `path`, keeping
+/// its last part as it is, so a link to a link still points at the link.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            Ok(fs::canonicalize(parent)?.join(name))
+        }
+        _ => fs::canonicalize(path),
+    }
+}
+
+/// Works out the path of `path` relative to the directory `base`.
+///
+/// Both paths are expected to be absolute and free of `..`, as `..` after a
+/// symbolic link does not lead back to where it started.
+fn relative_path(base: &Path, path: &Path) -> PathBuf {
+    let base: Vec<Component> = base.components().collect();
+    let path: Vec<Component> = path.components().collect();
+    let common = base.iter().zip(&path).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn relative_path_to_sibling() {
+        let path = relative_path(Path::new("/data/tgt"), Path::new("/data/src/x.txt"));
+        assert_eq!(path, Path::new("../src/x.txt"));
+    }
+
+    #[test]
+    fn relative_path_below_base() {
+        let path = relative_path(Path::new("/data"), Path::new("/data/src/a/x.txt"));
+        assert_eq!(path, Path::new("src/a/x.txt"));
+    }
+
+    #[test]
+    fn relative_path_in_same_directory() {
+        let path = relative_path(Path::new("/data/src"), Path::new("/data/src/x.txt"));
+        assert_eq!(path, Path::new("x.txt"));
+    }
+
+    #[test]
+    fn relative_path_from_deeper_directory() {
+        let path = relative_path(Path::new("/data/tgt/a/b"), Path::new("/data/src/x.txt"));
+        assert_eq!(path, Path::new("../../../src/x.txt"));
+    }
+
+    #[test]
+    fn copies_names_of_the_longest_length() {
+        let root = TempDir::new("transfer-long-name");
+        let name = format!("{}.txt", "x".repeat(251));
+        let source = root.write(&format!("src/{}", name), "hello");
+        fs::create_dir_all(root.join("tgt")).unwrap();
+
+        let target = root.join("tgt").join(&name);
+        transfer(TransferMode::Copy, &source, &target).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "hello");
+        assert!(find_temp_files(&root.join("tgt")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn copies_are_renamed_into_place_with_their_attributes() {
+        let root = TempDir::new("transfer-attributes");
+        let source = root.write("src/x.txt", "hello");
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        fs::create_dir_all(root.join("tgt")).unwrap();
+
+        let target = root.join("tgt/x.txt");
+        let unpreserved =
+            transfer_preserving(TransferMode::Copy, &source, &target, &[Attribute::Times]).unwrap();
+        assert_eq!(unpreserved, []);
+        assert_eq!(fs::metadata(&target).unwrap().modified().unwrap(), modified);
+    }
+
+    #[test]
+    fn rejected_copies_never_take_the_place_of_the_target() {
+        let root = TempDir::new("transfer-rejected");
+        let source = root.write("src/x.txt", "new");
+        let target = root.write("tgt/x.txt", "old");
+
+        let mut checked = None;
+        let result = transfer_checked(TransferMode::Copy, &source, &target, &[], |written| {
+            checked = Some(fs::read_to_string(written)?);
+            Ok(false)
+        });
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(checked.as_deref(), Some("new"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+        assert!(find_temp_files(&root.join("tgt")).unwrap().is_empty());
+
+        let result = transfer_checked(
+            TransferMode::Hardlink,
+            &source,
+            &root.join("tgt/y.txt"),
+            &[],
+            |_| Ok(false),
+        );
+        assert_eq!(result.unwrap(), None);
+        assert!(!root.join("tgt/y.txt").exists());
+        assert_eq!(fs::read_to_string(&source).unwrap(), "new");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relative_symlink_through_parent_directory() {
+        let root = TempDir::new("transfer-symlink");
+        let source = root.write("work/src/x.txt", "hello");
+        fs::create_dir_all(root.join("tgt")).unwrap();
+
+        let target = root.join("work/../tgt/x.txt");
+        transfer(TransferMode::SymlinkRelative, &source, &target).unwrap();
+
+        let link = fs::read_link(root.join("tgt/x.txt")).unwrap();
+        assert_eq!(link, Path::new("../work/src/x.txt"));
+        assert_eq!(fs::read_to_string(root.join("tgt/x.txt")).unwrap(), "hello");
+    }
+}
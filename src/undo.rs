@@ -0,0 +1,247 @@
+//! Reverting the files put in place by an earlier run.
+
+use crate::error::Error;
+use crate::existing::Action;
+use crate::journal::{Completed, History};
+use crate::transfer::{transfer, TransferMode};
+use crate::verify::hash_file;
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How a file put in place by a run is reverted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revert {
+    /// Delete the copy or link.
+    Delete,
+    /// Move the file back to where it came from.
+    MoveBack,
+}
+
+impl Revert {
+    /// Describes the revert, such as "deleting".
+    pub fn verb(self) -> &'static str {
+        match self {
+            Revert::Delete => "deleting",
+            Revert::MoveBack => "moving back",
+        }
+    }
+}
+
+/// Reasons a file cannot be reverted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UndoError {
+    /// The file is no longer where the run put it.
+    Missing(PathBuf),
+    /// The file no longer has the size or hash recorded by the run.
+    Changed(PathBuf),
+    /// The file replaced an existing file, which cannot be brought back.
+    Replaced(PathBuf),
+    /// A moved file cannot go back, as another file took its place.
+    SourceExists(PathBuf),
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UndoError::Missing(path) => write!(f, "`{}` no longer exists", path.display()),
+            UndoError::Changed(path) => {
+                write!(f, "`{}` changed since it was put in place", path.display())
+            }
+            UndoError::Replaced(path) => write!(
+                f,
+                "`{}` replaced an existing file, which cannot be restored",
+                path.display()
+            ),
+            UndoError::SourceExists(path) => write!(
+                f,
+                "Cannot move back to `{}`, another file is there",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl error::Error for UndoError {}
+
+/// Checks that the file recorded in `completed` is unchanged since the run
+/// put it in place, and works out how to revert it.
+pub fn check_undo(completed: &Completed) -> Result<Revert, Error> {
+    let target = &completed.target;
+    if matches!(completed.action, Action::Overwritten | Action::Updated) {
+        return Err(UndoError::Replaced(target.clone()).into());
+    }
+    match fs::metadata(target) {
+        Ok(metadata) if metadata.len() == completed.size => {}
+        Ok(_) => return Err(UndoError::Changed(target.clone()).into()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(UndoError::Missing(target.clone()).into())
+        }
+        Err(e) => return Err(Error::io(target, e)),
+    }
+    let hash = hash_file(completed.algorithm, target).map_err(|e| Error::io(target, e))?;
+    if hash != completed.hash {
+        return Err(UndoError::Changed(target.clone()).into());
+    }
+
+    if completed.mode != TransferMode::Move {
+        return Ok(Revert::Delete);
+    }
+    match fs::symlink_metadata(&completed.source) {
+        Ok(_) => Err(UndoError::SourceExists(completed.source.clone()).into()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Revert::MoveBack),
+        Err(e) => Err(Error::io(&completed.source, e)),
+    }
+}
+
+/// Reverts the file recorded in `completed` as checked by [`check_undo`],
+/// then removes the folders it leaves empty that were created by the runs
+/// in `history`, below any of their target directories.
+pub fn undo_file(completed: &Completed, revert: Revert, history: &History) -> Result<(), Error> {
+    let target = &completed.target;
+    match revert {
+        Revert::Delete => fs::remove_file(target).map_err(|e| Error::io(target, e))?,
+        Revert::MoveBack => {
+            let source = &completed.source;
+            if let Some(parent) = source.parent() {
+                fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+            }
+            transfer(TransferMode::Move, target, source).map_err(|error| Error::Transfer {
+                source: target.clone(),
+                target: source.clone(),
+                error,
+            })?;
+        }
+    }
+    remove_empty_parents(target, &history.target_dirs, &history.created_dirs);
+    Ok(())
+}
+
+/// Removes the parents of `path` that are empty and in `created`, up to but
+/// not including whichever of `roots` they are in. Folders that existed
+/// before the run are kept, even when empty.
+fn remove_empty_parents(path: &Path, roots: &[PathBuf], created: &HashSet<PathBuf>) {
+    for dir in path.ancestors().skip(1) {
+        let below_root = roots
+            .iter()
+            .any(|root| dir.starts_with(root) && dir != root);
+        if !below_root || !created.contains(dir) || fs::remove_dir(dir).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::read_history;
+    use crate::plan::Plan;
+    use crate::test_util::{execute_journaled, planned_copy, TempDir};
+
+    /// Puts `src/x.txt` in place at `target` below `tgt` with `mode`,
+    /// recording it in a journal, and returns what the journal recorded.
+    fn run(root: &TempDir, target: &str, mode: TransferMode) -> History {
+        root.write("src/x.txt", "hello");
+        fs::create_dir_all(root.join("tgt")).unwrap();
+        let mut copy = planned_copy(root.join("src/x.txt"), root.join("tgt").join(target));
+        copy.mode = mode;
+        let mut plan = Plan {
+            target_dir: root.join("tgt"),
+            copies: vec![copy],
+            ..Plan::default()
+        };
+        let path = root.join("journal");
+        let execution = execute_journaled(&mut plan, &path);
+        assert!(execution.outcomes[0].result.is_ok());
+        read_history(&path).unwrap()
+    }
+
+    fn undo_error(completed: &Completed) -> UndoError {
+        match check_undo(completed) {
+            Err(Error::Undo(e)) => e,
+            result => panic!("expected an undo error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn changed_and_missing_files_are_refused() {
+        let root = TempDir::new("undo-changed");
+        let history = run(&root, "x.txt", TransferMode::Copy);
+        let completed = &history.completed[0];
+        let target = root.join("tgt/x.txt");
+        assert_eq!(check_undo(completed).unwrap(), Revert::Delete);
+
+        fs::write(&target, "HELLO").unwrap();
+        assert_eq!(undo_error(completed), UndoError::Changed(target.clone()));
+        fs::write(&target, "hi").unwrap();
+        assert_eq!(undo_error(completed), UndoError::Changed(target.clone()));
+        fs::remove_file(&target).unwrap();
+        assert_eq!(undo_error(completed), UndoError::Missing(target));
+    }
+
+    #[test]
+    fn replaced_files_are_refused() {
+        let root = TempDir::new("undo-replaced");
+        let history = run(&root, "x.txt", TransferMode::Copy);
+        for action in [Action::Overwritten, Action::Updated] {
+            let completed = Completed {
+                action,
+                ..history.completed[0].clone()
+            };
+            assert_eq!(
+                undo_error(&completed),
+                UndoError::Replaced(root.join("tgt/x.txt"))
+            );
+        }
+    }
+
+    #[test]
+    fn moved_files_are_moved_back() {
+        let root = TempDir::new("undo-moved");
+        let history = run(&root, "x.txt", TransferMode::Move);
+        let completed = &history.completed[0];
+        assert!(!root.join("src/x.txt").exists());
+
+        root.write("src/x.txt", "other");
+        assert_eq!(
+            undo_error(completed),
+            UndoError::SourceExists(root.join("src/x.txt"))
+        );
+        fs::remove_file(root.join("src/x.txt")).unwrap();
+
+        let revert = check_undo(completed).unwrap();
+        assert_eq!(revert, Revert::MoveBack);
+        undo_file(completed, revert, &history).unwrap();
+        assert_eq!(fs::read_to_string(root.join("src/x.txt")).unwrap(), "hello");
+        assert!(!root.join("tgt/x.txt").exists());
+    }
+
+    #[test]
+    fn only_folders_created_by_the_run_are_removed() {
+        let root = TempDir::new("undo-folders");
+        fs::create_dir_all(root.join("tgt/old")).unwrap();
+        let history = run(&root, "old/new/deeper/x.txt", TransferMode::Copy);
+        assert_eq!(
+            history.created_dirs,
+            HashSet::from([root.join("tgt/old/new"), root.join("tgt/old/new/deeper")])
+        );
+
+        let completed = &history.completed[0];
+        undo_file(completed, check_undo(completed).unwrap(), &history).unwrap();
+        assert!(!root.join("tgt/old/new").exists());
+        assert!(root.join("tgt/old").is_dir());
+    }
+
+    #[test]
+    fn remove_empty_parents_stops_at_the_target_root() {
+        let root = TempDir::new("undo-root");
+        fs::create_dir_all(root.join("tgt/a/b")).unwrap();
+        let created = HashSet::from([root.join("tgt"), root.join("tgt/a"), root.join("tgt/a/b")]);
+        remove_empty_parents(&root.join("tgt/a/b/x.txt"), &[root.join("tgt")], &created);
+        assert!(!root.join("tgt/a").exists());
+        assert!(root.join("tgt").is_dir());
+    }
+}
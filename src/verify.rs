@@ -0,0 +1,124 @@
+//! Hashing files to prove a copy matches its source.
+
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Hash function used to compare files and write manifests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    /// SHA-256, as written by `sha256sum`.
+    #[default]
+    Sha256,
+    /// BLAKE3, as written by `b3sum`.
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Name of the hash function, such as "SHA-256".
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "SHA-256",
+            HashAlgorithm::Blake3 => "BLAKE3",
+        }
+    }
+}
+
+/// A hex digest of a file, and the hash function that made it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    /// Hash function used.
+    pub algorithm: HashAlgorithm,
+    /// Lower-case hex digest.
+    pub digest: String,
+}
+
+/// Hashes the contents of the file at `path`, returning a lower-case hex
+/// digest.
+pub fn hash_file(algorithm: HashAlgorithm, path: &Path) -> io::Result<String> {
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            read_chunks(path, |chunk| hasher.update(chunk))?;
+            Ok(format!("{:x}", hasher.finalize()))
+        }
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(path, |chunk| {
+                hasher.update(chunk);
+            })?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+    }
+}
+
+/// Feeds the contents of the file at `path` to `consume`, a chunk at a time.
+fn read_chunks<F: FnMut(&[u8])>(path: &Path, mut consume: F) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        consume(&buffer[..read]);
+    }
+}
+
+/// Writes a checksum manifest that `sha256sum -c` (or `b3sum -c`) can check.
+///
+/// Each entry is a hex digest and a file path. Paths below the directory
+/// holding the manifest are written relative to it, so the manifest can be
+/// checked from that directory. Paths holding a `\` or a line break are
+/// escaped as coreutils does, starting their line with `\`.
+pub fn write_manifest<P: AsRef<Path>>(path: P, entries: &[(String, &Path)]) -> Result<(), Error> {
+    let path = path.as_ref();
+    let write = || {
+        let path = std::path::absolute(path)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for (digest, file) in entries {
+            let file = file.strip_prefix(base).unwrap_or(file);
+            let name = file.display().to_string();
+            if name.contains(['\\', '\n']) {
+                let name = name.replace('\\', "\\\\").replace('\n', "\\n");
+                writeln!(writer, "\\{}  {}", digest, name)?;
+            } else {
+                writeln!(writer, "{}  {}", digest, name)?;
+            }
+        }
+        writer.flush()
+    };
+    write().map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn names_with_backslashes_and_line_breaks_are_escaped() {
+        let root = TempDir::new("verify-manifest");
+        let path = root.join("SHA256SUMS");
+        let entries = [
+            ("aa".to_string(), root.join("plain.txt")),
+            ("bb".to_string(), root.join("two\nlines.txt")),
+            ("cc".to_string(), root.join("back\\slash.txt")),
+        ];
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(digest, file)| (digest.clone(), file.as_path()))
+            .collect();
+        write_manifest(&path, &entries).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "aa  plain.txt\n\\bb  two\\nlines.txt\n\\cc  back\\\\slash.txt\n"
+        );
+    }
+}